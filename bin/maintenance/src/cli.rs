@@ -0,0 +1,21 @@
+use clap::{Parser, Subcommand};
+
+/// Maintenance tasks for the stored consumption data.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+	#[command(subcommand)]
+	pub command: Command,
+}
+
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+	/// Checks all the consumption files for malformed rows, non-monotonic block numbers,
+	/// duplicate blocks and interrupted writes.
+	Verify,
+	/// Fixes all the issues found by `verify`.
+	Repair,
+	/// Rewrites all the consumption files written with an older schema version using the
+	/// current one.
+	Migrate,
+}
@@ -0,0 +1,162 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+use clap::Parser;
+use cli::Command;
+use shared::{
+	config::config,
+	consumption::{
+		migrate_consumption, repair_consumption, verify_consumption, IntegrityReport,
+		SCHEMA_VERSION,
+	},
+	registry::registered_paras,
+};
+use std::io::ErrorKind;
+use types::Parachain;
+
+mod cli;
+
+const LOG_TARGET: &str = "maintenance";
+
+fn main() {
+	env_logger::init();
+
+	let args = cli::Args::parse();
+
+	let outputs = config().outputs;
+	// `None` stands for the processed output, the rest are the outputs of the trackers.
+	let rpc_indexes: Vec<Option<usize>> =
+		std::iter::once(None).chain((0..outputs).map(Some)).collect();
+
+	let paras = registered_paras().unwrap_or_else(|e| {
+		log::error!(target: LOG_TARGET, "Failed to read the registry: {:?}", e);
+		std::process::exit(1);
+	});
+
+	let mut healthy = true;
+	for para in paras {
+		for rpc_index in rpc_indexes.iter() {
+			healthy &= match args.command {
+				Command::Migrate => migrate(para.clone(), *rpc_index),
+				command => check_consumption(para.clone(), *rpc_index, command),
+			};
+		}
+	}
+
+	if args.command != Command::Repair && !healthy {
+		std::process::exit(1);
+	}
+}
+
+/// Verifies or repairs a single consumption file. Returns whether the file had no issues.
+fn check_consumption(para: Parachain, rpc_index: Option<usize>, command: Command) -> bool {
+	let result = if command == Command::Repair {
+		repair_consumption(para.clone(), rpc_index)
+	} else {
+		verify_consumption(para.clone(), rpc_index)
+	};
+
+	match result {
+		Ok(report) if report.is_ok() => {
+			log::info!(
+				target: LOG_TARGET,
+				"{}-{} - Output {:?}: {} rows, no issues found.",
+				para.relay_chain,
+				para.para_id,
+				rpc_index,
+				report.rows,
+			);
+			true
+		},
+		Ok(report) => {
+			log_issues(&para, rpc_index, &report, command);
+			false
+		},
+		// Not all the outputs necessarily exist.
+		Err(e) if e.kind() == ErrorKind::NotFound => true,
+		Err(e) => {
+			log::error!(
+				target: LOG_TARGET,
+				"{}-{} - Output {:?}: Failed to check consumption: {:?}",
+				para.relay_chain,
+				para.para_id,
+				rpc_index,
+				e,
+			);
+			false
+		},
+	}
+}
+
+/// Migrates a single consumption file to the current schema version. Returns whether the
+/// migration succeeded.
+fn migrate(para: Parachain, rpc_index: Option<usize>) -> bool {
+	match migrate_consumption(para.clone(), rpc_index) {
+		Ok(version) => {
+			log::info!(
+				target: LOG_TARGET,
+				"{}-{} - Output {:?}: Migrated from schema version {} to {}.",
+				para.relay_chain,
+				para.para_id,
+				rpc_index,
+				version,
+				SCHEMA_VERSION,
+			);
+			true
+		},
+		Err(e) if e.kind() == ErrorKind::NotFound => true,
+		Err(e) => {
+			log::error!(
+				target: LOG_TARGET,
+				"{}-{} - Output {:?}: Failed to migrate consumption: {:?}",
+				para.relay_chain,
+				para.para_id,
+				rpc_index,
+				e,
+			);
+			false
+		},
+	}
+}
+
+fn log_issues(
+	para: &Parachain,
+	rpc_index: Option<usize>,
+	report: &IntegrityReport,
+	command: Command,
+) {
+	log::warn!(
+		target: LOG_TARGET,
+		"{}-{} - Output {:?}: {} rows, malformed lines: {:?}, out of order lines: {:?}, duplicate lines: {:?}, interrupted write: {}",
+		para.relay_chain,
+		para.para_id,
+		rpc_index,
+		report.rows,
+		report.malformed,
+		report.out_of_order,
+		report.duplicates,
+		report.torn,
+	);
+
+	if command == Command::Repair {
+		log::info!(
+			target: LOG_TARGET,
+			"{}-{} - Output {:?}: Repaired.",
+			para.relay_chain,
+			para.para_id,
+			rpc_index,
+		);
+	}
+}
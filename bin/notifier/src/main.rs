@@ -0,0 +1,50 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! # Notifier
+//!
+//! Periodically scans the registry and sends a webhook when the subscription of a parachain
+//! enters its renewal window, expires or reaches the end of its grace period.
+
+use shared::{config::config, notifier::notify};
+use std::{thread, time::Duration};
+
+const LOG_TARGET: &str = "notifier";
+
+fn main() {
+	env_logger::init();
+
+	let Some(notifier) = config().notifier else {
+		log::error!(target: LOG_TARGET, "The notifier isn't configured.");
+		return;
+	};
+
+	loop {
+		match notify(&notifier) {
+			Ok(notified) => notified.iter().for_each(|notification| {
+				log::info!(
+					target: LOG_TARGET,
+					"{}-{} - Sent {:?} notification",
+					notification.para.0,
+					notification.para.1,
+					notification.kind
+				);
+			}),
+			Err(e) => log::error!(target: LOG_TARGET, "Failed to send notifications: {:?}", e),
+		}
+
+		thread::sleep(Duration::from_secs(notifier.interval));
+	}
+}
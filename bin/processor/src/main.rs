@@ -14,12 +14,18 @@
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
 use shared::{
+	archive::rotate_consumption,
 	config::config,
-	consumption::{delete_consumption, get_consumption, write_batch_consumption},
+	consumption::{
+		delete_consumption, get_consumption, get_live_consumption, prune_consumption,
+		write_batch_consumption,
+	},
+	current_timestamp,
 	registry::registered_paras,
+	rollup::update_rollups,
 };
 use std::collections::BTreeMap;
-use types::WeightConsumption;
+use types::{Parachain, RollupPeriod, Timestamp, WeightConsumption};
 
 const LOG_TARGET: &str = "processor";
 
@@ -27,7 +33,13 @@ fn main() {
 	env_logger::init();
 
 	let outputs = config().outputs;
-	let paras = registered_paras();
+	let paras = match registered_paras() {
+		Ok(paras) => paras,
+		Err(e) => {
+			log::error!(target: LOG_TARGET, "Failed to read the registry: {:?}", e);
+			return;
+		},
+	};
 
 	paras.iter().for_each(|para| {
 		let mut processed = BTreeMap::new();
@@ -41,7 +53,7 @@ fn main() {
 
 		(0..outputs).for_each(|output_index| {
 			let consumption = if let Ok(data) = get_consumption(para.clone(), Some(output_index)) {
-				data
+				data.entries
 			} else {
 				log::error!(
 					target: LOG_TARGET,
@@ -80,5 +92,78 @@ fn main() {
 		}
 
 		(0..outputs).for_each(|output_index| delete_consumption(para.clone(), output_index));
+
+		rollup_consumption(para.clone());
 	});
 }
+
+/// Rolls up the processed consumption data of a parachain, archives the completed months and
+/// prunes the per-block data which is older than the configured retention period.
+fn rollup_consumption(para: Parachain) {
+	let Ok(consumption) = get_live_consumption(para.clone(), None).map(|data| data.entries) else {
+		log::error!(
+			target: LOG_TARGET,
+			"{}-{} - Failed to get processed consumption.",
+			para.relay_chain,
+			para.para_id,
+		);
+		return;
+	};
+
+	for period in RollupPeriod::ALL {
+		if let Err(e) = update_rollups(para.clone(), period, &consumption) {
+			log::error!(
+				target: LOG_TARGET,
+				"{}-{} - Failed to update {} rollups: {:?}",
+				para.relay_chain,
+				para.para_id,
+				period,
+				e,
+			);
+
+			// Never archive or prune data which hasn't been rolled up.
+			return;
+		}
+	}
+
+	if let Err(e) = rotate_consumption(para.clone(), current_timestamp().saturating_mul(1000)) {
+		log::error!(
+			target: LOG_TARGET,
+			"{}-{} - Failed to archive consumption: {:?}",
+			para.relay_chain,
+			para.para_id,
+			e,
+		);
+	}
+
+	let Some(retention_period) = retention_period(&para) else { return };
+
+	// Only whole days are pruned so that none of the rollups can end up covering a partially
+	// pruned period.
+	let cutoff = current_timestamp().saturating_sub(retention_period).saturating_mul(1000);
+	let cutoff = RollupPeriod::Day.period_start(cutoff);
+
+	if let Err(e) = prune_consumption(para.clone(), cutoff) {
+		log::error!(
+			target: LOG_TARGET,
+			"{}-{} - Failed to prune consumption: {:?}",
+			para.relay_chain,
+			para.para_id,
+			e,
+		);
+	}
+}
+
+/// The retention period of the parachain's per-block data, which can be overridden by its plan.
+fn retention_period(para: &Parachain) -> Option<Timestamp> {
+	let config = config();
+
+	let plan_retention = para.plan.as_ref().and_then(|name| {
+		config
+			.payment_info_for(&para.relay_chain)?
+			.plan(name)
+			.and_then(|plan| plan.retention_period)
+	});
+
+	plan_retention.or(config.retention_period)
+}
@@ -20,8 +20,12 @@
 /// - `/register`: Used to register a parachain for consumption tracking.
 use rocket_cors::CorsOptions;
 use routes::{
-	consumption::consumption, extend_subscription::extend_subscription, register::register_para,
-	registry::registry,
+	admin::{patch_para, remove_para, set_expiry},
+	consumption::consumption,
+	extend_subscription::extend_subscription,
+	register::register_para,
+	registry::{registry, registry_history},
+	subscription::subscription,
 };
 
 #[macro_use]
@@ -31,7 +35,19 @@ extern crate rocket;
 fn rocket() -> _ {
 	rocket::build()
 		.attach(CorsOptions::default().to_cors().unwrap())
-
-		.mount("/", routes![consumption, register_para, registry, extend_subscription])
-
+		.mount(
+			"/",
+			routes![
+				consumption,
+				register_para,
+				registry,
+				registry_history,
+				extend_subscription,
+				subscription,
+				remove_para,
+				patch_para,
+				set_expiry
+			],
+		)
+		.register("/", routes::catchers())
 }
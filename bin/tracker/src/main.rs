@@ -39,14 +39,14 @@
 //! | ...          | ...                   | ...                       | ...                           | ...                         | ...               | ...                     | ...                   |
 //!
 //! The percentages themselves are stored by representing them as decimal numbers;
-//! for example, 50.5% is stored as 0.505 with a precision of three decimals.
+//! for example, 50.5% is stored as 0.505. The values are stored with full precision.
 
 const LOG_TARGET: &str = "tracker";
 
 const LOG_TARGET: &str = "tracker";
 
 use clap::Parser;
-use shared::{consumption::write_consumption, registry::registered_paras, round_to};
+use shared::{consumption::write_consumption, registry::registered_paras};
 use subxt::{blocks::Block, utils::H256, OnlineClient, PolkadotConfig};
 use types::{Parachain, Timestamp, WeightConsumption};
 
@@ -64,6 +64,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	// Asynchronously subscribes to follow the latest finalized block of each parachain
 	// and continuously fetches the weight consumption.
 	let tasks: Vec<_> = registered_paras()
+		.map_err(|e| format!("Failed to read the registry: {:?}", e))?
 		.into_iter()
 		.map(|para| {
 			tokio::spawn(async move { track_weight_consumption(para, args.rpc_index).await })
@@ -187,15 +188,15 @@ async fn weight_consumption(
 		block_number,
 		timestamp,
 		ref_time: (
-			round_to(normal_ref_time as f32 / ref_time_limit as f32, 3),
-			round_to(operational_ref_time as f32 / ref_time_limit as f32, 3),
-			round_to(mandatory_ref_time as f32 / ref_time_limit as f32, 3),
+			normal_ref_time as f64 / ref_time_limit as f64,
+			operational_ref_time as f64 / ref_time_limit as f64,
+			mandatory_ref_time as f64 / ref_time_limit as f64,
 		)
 			.into(),
 		proof_size: (
-			round_to(normal_proof_size as f32 / proof_limit as f32, 3),
-			round_to(operational_proof_size as f32 / proof_limit as f32, 3),
-			round_to(mandatory_proof_size as f32 / proof_limit as f32, 3),
+			normal_proof_size as f64 / proof_limit as f64,
+			operational_proof_size as f64 / proof_limit as f64,
+			mandatory_proof_size as f64 / proof_limit as f64,
 		)
 			.into(),
 	};
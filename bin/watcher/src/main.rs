@@ -0,0 +1,138 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! # Payment Watcher
+//!
+//! Follows the finalized blocks of the payment chain and applies every payment made to the
+//! `receiver`, so that the payers don't have to register or extend the subscription of their
+//! parachain themselves.
+
+use futures::{future::join_all, stream::BoxStream, StreamExt};
+use polkadot_core_primitives::BlockNumber;
+use shared::{
+	config::{config, PaymentInfo},
+	payment::{block_payments_at, get_last_finalized_block},
+	watcher::{watch_payments, DetectedPayment, PaymentChain, WatcherError},
+};
+use std::time::Duration;
+use subxt::{backend::rpc::RpcClient, blocks::Block, OnlineClient, PolkadotConfig};
+use types::RelayChain;
+
+const LOG_TARGET: &str = "watcher";
+
+/// The delay before restarting a crashed payment watcher.
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+type PaymentBlock = Block<PolkadotConfig, OnlineClient<PolkadotConfig>>;
+
+#[tokio::main]
+async fn main() {
+	env_logger::init();
+
+	let payment_info = config().payment_info;
+	if payment_info.is_empty() {
+		log::error!(target: LOG_TARGET, "Payments are disabled, there is nothing to watch.");
+		return;
+	}
+
+	// Each relay chain has its own payment chain.
+	join_all(
+		payment_info
+			.into_iter()
+			.map(|(relay_chain, payment_info)| supervise(relay_chain, payment_info)),
+	)
+	.await;
+}
+
+/// Watches the payments of the payment chain in a separate task, which gets restarted should it
+/// panic, without affecting the watchers of the other payment chains.
+async fn supervise(relay_chain: RelayChain, payment_info: PaymentInfo) {
+	loop {
+		let task = tokio::spawn(watch(relay_chain.clone(), payment_info.clone()));
+
+		if let Err(err) = task.await {
+			log::error!(
+				target: LOG_TARGET,
+				"{} - The payment watcher crashed, restarting it in {}s: {:?}",
+				relay_chain,
+				RESTART_DELAY.as_secs(),
+				err
+			);
+		}
+
+		tokio::time::sleep(RESTART_DELAY).await;
+	}
+}
+
+async fn watch(relay_chain: RelayChain, payment_info: PaymentInfo) {
+	let connect = || SubxtPaymentChain::connect(&relay_chain, &payment_info);
+	watch_payments(relay_chain.clone(), payment_info.clone(), connect).await
+}
+
+/// The payment chain, followed through its rpc node.
+struct SubxtPaymentChain {
+	rpc_client: RpcClient,
+	api: OnlineClient<PolkadotConfig>,
+	finalized_blocks: BoxStream<'static, Result<PaymentBlock, subxt::Error>>,
+	payment_info: PaymentInfo,
+}
+
+impl SubxtPaymentChain {
+	async fn connect(
+		relay_chain: &RelayChain,
+		payment_info: &PaymentInfo,
+	) -> Result<Self, WatcherError> {
+		log::info!(
+			target: LOG_TARGET,
+			"{} - Subscribing to finalized blocks of the payment chain",
+			relay_chain
+		);
+
+		let rpc_client = RpcClient::from_url(&payment_info.rpc_url).await.map_err(rpc_error)?;
+		let api = OnlineClient::<PolkadotConfig>::from_url(&payment_info.rpc_url)
+			.await
+			.map_err(rpc_error)?;
+		let finalized_blocks = api.blocks().subscribe_finalized().await.map_err(rpc_error)?.boxed();
+
+		Ok(Self { rpc_client, api, finalized_blocks, payment_info: payment_info.clone() })
+	}
+}
+
+impl PaymentChain for SubxtPaymentChain {
+	async fn finalized_block(&self) -> Result<BlockNumber, WatcherError> {
+		get_last_finalized_block(self.rpc_client.clone(), self.api.clone())
+			.await
+			.map_err(WatcherError::Payment)
+	}
+
+	async fn next_finalized_block(&mut self) -> Option<Result<BlockNumber, WatcherError>> {
+		let block = self.finalized_blocks.next().await?;
+		Some(block.map(|block| block.number()).map_err(rpc_error))
+	}
+
+	async fn payments_at(
+		&self,
+		block_number: BlockNumber,
+	) -> Result<Vec<DetectedPayment>, WatcherError> {
+		block_payments_at(&self.rpc_client, &self.api, block_number, &self.payment_info)
+			.await
+			.map_err(WatcherError::Payment)
+	}
+}
+
+fn rpc_error(err: subxt::Error) -> WatcherError {
+	log::error!(target: LOG_TARGET, "Payment chain rpc error: {:?}", err);
+	WatcherError::Rpc
+}
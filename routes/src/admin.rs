@@ -0,0 +1,151 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Routes for manually managing the registry.
+//!
+//! All the routes require the `admin_token` from the config to be provided as a bearer token.
+//! The routes are disabled if no `admin_token` is configured.
+
+use crate::*;
+use rocket::{
+	delete, patch, put,
+	request::{FromRequest, Outcome},
+	serde::json::Json,
+};
+use shared::{audit::ChangeOrigin, config::config, registry::modify_registry};
+use subtle::ConstantTimeEq;
+use types::{ParaId, Parachain, RelayChain, Timestamp};
+
+const ADMIN: &str = "admin";
+
+/// Request guard ensuring that the caller provided the admin token.
+pub struct AdminAuth;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminAuth {
+	type Error = Error;
+
+	async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+		let Some(admin_token) = config().admin_token else {
+			return Outcome::Error((Status::Unauthorized, Error::Unauthorized));
+		};
+
+		let provided = request
+			.headers()
+			.get_one("Authorization")
+			.and_then(|header| header.strip_prefix("Bearer "));
+
+		// Compared in constant time so the token can't be guessed from the response times.
+		let authorized = provided
+			.is_some_and(|provided| bool::from(provided.as_bytes().ct_eq(admin_token.as_bytes())));
+
+		if authorized {
+			Outcome::Success(AdminAuth)
+		} else {
+			Outcome::Error((Status::Unauthorized, Error::Unauthorized))
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ParaUpdate {
+	/// The new name of the parachain.
+	pub name: Option<String>,
+	/// The new list of rpcs of the parachain.
+	pub rpcs: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ExpiryData {
+	/// The timestamp when the subscription expires.
+	pub expiry_timestamp: Timestamp,
+}
+
+/// Remove a parachain from the registry.
+#[delete("/admin/registry/<relay>/<para_id>")]
+pub fn remove_para(_admin: AdminAuth, relay: &str, para_id: ParaId) -> Result<(), Error> {
+	update_para(relay_chain(relay)?, para_id, |paras, index| {
+		paras.remove(index);
+	})
+}
+
+/// Update the name or the rpcs of a registered parachain.
+#[patch("/admin/registry/<relay>/<para_id>", data = "<data>")]
+pub fn patch_para(
+	_admin: AdminAuth,
+	relay: &str,
+	para_id: ParaId,
+	data: Json<ParaUpdate>,
+) -> Result<(), Error> {
+	let ParaUpdate { name, rpcs } = data.into_inner();
+
+	update_para(relay_chain(relay)?, para_id, |paras, index| {
+		if let Some(name) = name {
+			paras[index].name = name;
+		}
+		if let Some(rpcs) = rpcs {
+			paras[index].rpcs = rpcs;
+		}
+	})
+}
+
+/// Set the expiry of a registered parachain.
+#[put("/admin/registry/<relay>/<para_id>/expiry", data = "<data>")]
+pub fn set_expiry(
+	_admin: AdminAuth,
+	relay: &str,
+	para_id: ParaId,
+	data: Json<ExpiryData>,
+) -> Result<(), Error> {
+	update_para(relay_chain(relay)?, para_id, |paras, index| {
+		paras[index].expiry_timestamp = data.expiry_timestamp;
+	})
+}
+
+/// Applies `f` to the registry entry of the parachain and reloads the tracker.
+fn update_para(
+	relay_chain: RelayChain,
+	para_id: ParaId,
+	f: impl FnOnce(&mut Vec<Parachain>, usize),
+) -> Result<(), Error> {
+	let origin = ChangeOrigin { actor: ADMIN.into(), payment_block_number: None };
+
+	modify_registry(&origin, |paras| -> Result<(), Error> {
+		let index = paras
+			.iter()
+			.position(|para| para.relay_chain == relay_chain && para.para_id == para_id)
+			.ok_or(Error::NotRegistered)?;
+
+		f(paras, index);
+		Ok(())
+	})
+	.map_err(|err| {
+		log::error!(
+			target: LOG_TARGET,
+			"{}-{} - Failed to update para: {:?}",
+			relay_chain,
+			para_id,
+			err
+		);
+		err
+	})?;
+
+	#[cfg(not(debug_assertions))]
+	shared::init_tracker();
+
+	Ok(())
+}
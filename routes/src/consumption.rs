@@ -13,17 +13,27 @@
 // You should have received a copy of the GNU General Public License
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::Error;
-use chrono::NaiveDateTime;
+use crate::{relay_chain, Error};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
 use rocket::{
-	form,
-	form::{FromFormField, ValueField},
 	get,
+	http::{Accept, ContentType},
+	response::stream::TextStream,
+	tokio::{sync::mpsc, task::spawn_blocking},
 };
-use shared::{consumption::get_consumption, registry::registered_para};
-use std::collections::BTreeMap;
+use shared::{
+	consumption::{csv_content, read_consumption_between},
+	registry::registered_para,
+	rollup::{get_rollups, percentile},
+	round_to,
+};
+use std::{collections::BTreeMap, str::FromStr};
 
-use types::{DispatchClassConsumption, ParaId, Timestamp, WeightConsumption};
+use types::{
+	ConsumptionRollup, DispatchClassConsumption, DispatchClassPercentiles, DispatchClassStats,
+	ParaId, Parachain, RollupPeriod, Timestamp, WeightConsumption,
+};
 
 #[derive(Clone, Debug, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -32,24 +42,175 @@ pub enum Grouping {
 	Minute,
 	Hour,
 	Day,
+	/// ISO weeks, starting on Monday.
+	Week,
 	Month,
+	Quarter,
 	Year,
+	/// Custom sized buckets.
+	Bucket(Bucket),
 }
 
-#[rocket::async_trait]
-impl<'r> FromFormField<'r> for Grouping {
-	fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
-		match field.value {
+impl FromStr for Grouping {
+	type Err = ();
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"block_number" => Ok(Grouping::BlockNumber),
 			"minute" => Ok(Grouping::Minute),
 			"hour" => Ok(Grouping::Hour),
 			"day" => Ok(Grouping::Day),
+			"week" => Ok(Grouping::Week),
 			"month" => Ok(Grouping::Month),
+			"quarter" => Ok(Grouping::Quarter),
 			"year" => Ok(Grouping::Year),
-			_ => Err(form::Error::validation("invalid Grouping").into()),
+			_ => Err(()),
+		}
+	}
+}
+
+/// A custom bucket size, e.g. `15m`, `6h`, `7d` or `100b`.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub enum Bucket {
+	/// Buckets spanning the given number of milliseconds.
+	Interval(Timestamp),
+	/// Buckets of the given number of blocks.
+	Blocks(u32),
+}
+
+impl FromStr for Bucket {
+	type Err = ();
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let unit_index = value.find(|c: char| !c.is_ascii_digit()).ok_or(())?;
+		let (size, unit) = value.split_at(unit_index);
+		let size: u32 = size.parse().map_err(|_| ())?;
+		if size == 0 {
+			return Err(());
+		}
+
+		let unit_duration: Timestamp = match unit {
+			"b" => return Ok(Bucket::Blocks(size)),
+			"s" => 1000,
+			"m" => 60 * 1000,
+			"h" => RollupPeriod::Hour.duration(),
+			"d" => RollupPeriod::Day.duration(),
+			"w" => 7 * RollupPeriod::Day.duration(),
+			_ => return Err(()),
+		};
+
+		Ok(Bucket::Interval(size as Timestamp * unit_duration))
+	}
+}
+
+/// The timezone to use for the time based grouping.
+///
+/// Either an IANA zone name, e.g. `Europe/Berlin`, or a fixed offset from UTC, e.g. `-05:00`.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum Timezone {
+	/// A fixed offset from UTC.
+	Fixed(FixedOffset),
+	/// A zone whose offset depends on the date, e.g. because of the daylight saving time.
+	Zone(Tz),
+}
+
+impl Timezone {
+	pub fn utc() -> Self {
+		Timezone::Fixed(FixedOffset::east_opt(0).expect("Zero is a valid offset"))
+	}
+
+	/// Returns the offset of the timezone at the given date and time in UTC.
+	pub fn offset_at(&self, datetime: &NaiveDateTime) -> FixedOffset {
+		match self {
+			Timezone::Fixed(offset) => *offset,
+			Timezone::Zone(tz) => tz.offset_from_utc_datetime(datetime).fix(),
+		}
+	}
+}
+
+impl FromStr for Timezone {
+	type Err = ();
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"UTC" | "utc" | "Z" => Ok(Timezone::utc()),
+			value => value
+				.parse()
+				.map(Timezone::Fixed)
+				.or_else(|_| value.parse().map(Timezone::Zone))
+				.map_err(|_| ()),
+		}
+	}
+}
+
+/// A statistic of the consumption of the blocks within a group.
+#[derive(
+	Clone, Debug, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Statistic {
+	Mean,
+	Min,
+	Max,
+	/// The median.
+	P50,
+	P90,
+	P99,
+}
+
+impl Statistic {
+	pub const ALL: [Statistic; 6] = [
+		Statistic::Mean,
+		Statistic::Min,
+		Statistic::Max,
+		Statistic::P50,
+		Statistic::P90,
+		Statistic::P99,
+	];
+
+	/// The percentile the statistic refers to, if any.
+	fn percentile(&self) -> Option<usize> {
+		match self {
+			Statistic::P50 => Some(50),
+			Statistic::P90 => Some(90),
+			Statistic::P99 => Some(99),
+			Statistic::Mean | Statistic::Min | Statistic::Max => None,
+		}
+	}
+
+	/// The name of the statistic, as used in the query.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Statistic::Mean => "mean",
+			Statistic::Min => "min",
+			Statistic::Max => "max",
+			Statistic::P50 => "p50",
+			Statistic::P90 => "p90",
+			Statistic::P99 => "p99",
 		}
 	}
 }
 
+/// The statistics to return, provided as a comma separated list, e.g. `mean,max,p99`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Statistics(pub Vec<Statistic>);
+
+impl FromStr for Statistics {
+	type Err = ();
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		value
+			.split(',')
+			.filter(|stat| !stat.is_empty())
+			.map(|stat| {
+				Statistic::ALL.into_iter().find(|statistic| statistic.name() == stat).ok_or(())
+			})
+			.collect::<Result<Vec<Statistic>, ()>>()
+			.map(Statistics)
+	}
+}
+
 #[derive(Default, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct AggregatedData {
@@ -59,83 +220,729 @@ pub struct AggregatedData {
 	/// The aggregated proof size over all dispatch classes.
 	pub proof_size: DispatchClassConsumption,
 	pub count: usize,
+	/// The first block within the group.
+	pub first_block: u32,
+	/// The last block within the group.
+	pub last_block: u32,
+	/// The statistics of the ref_time consumption of the blocks within the group.
+	#[serde(default)]
+	pub ref_time_stats: BTreeMap<Statistic, DispatchClassConsumption>,
+	/// The statistics of the proof size of the blocks within the group.
+	#[serde(default)]
+	pub proof_size_stats: BTreeMap<Statistic, DispatchClassConsumption>,
+}
+
+impl AggregatedData {
+	/// Rounds all the consumption values to the given number of decimals.
+	fn round(&mut self, decimals: u32) {
+		[&mut self.ref_time, &mut self.proof_size]
+			.into_iter()
+			.chain(self.ref_time_stats.values_mut())
+			.chain(self.proof_size_stats.values_mut())
+			.for_each(|consumption| {
+				consumption.normal = round_to(consumption.normal, decimals as i32);
+				consumption.operational = round_to(consumption.operational, decimals as i32);
+				consumption.mandatory = round_to(consumption.mandatory, decimals as i32);
+			});
+	}
+
+	/// Returns the group as a vector of strings, where each element represents a column in a CSV
+	/// format. The columns follow the ones of `csv_header`.
+	pub fn to_csv(&self, stats: &[Statistic]) -> Vec<String> {
+		let mut record = vec![
+			self.group.clone(),
+			self.first_block.to_string(),
+			self.last_block.to_string(),
+			self.count.to_string(),
+		];
+
+		[&self.ref_time, &self.proof_size].into_iter().for_each(|consumption| {
+			record.push(consumption.normal.to_string());
+			record.push(consumption.operational.to_string());
+			record.push(consumption.mandatory.to_string());
+		});
+
+		[&self.ref_time_stats, &self.proof_size_stats]
+			.into_iter()
+			.for_each(|statistics| {
+				stats.iter().for_each(|stat| match statistics.get(stat) {
+					Some(consumption) => {
+						record.push(consumption.normal.to_string());
+						record.push(consumption.operational.to_string());
+						record.push(consumption.mandatory.to_string());
+					},
+					// The statistic isn't available for the group.
+					None => record.extend(vec![String::new(); 3]),
+				})
+			});
+
+		record
+	}
+
+	/// Only keeps the given statistics.
+	fn retain_stats(&mut self, stats: &[Statistic]) {
+		self.ref_time_stats.retain(|stat, _| stats.contains(stat));
+		self.proof_size_stats.retain(|stat, _| stats.contains(stat));
+	}
+
+	/// Converts a daily rollup into the aggregated data of the corresponding day.
+	fn from_daily_rollup(rollup: ConsumptionRollup) -> Self {
+		let stats = |stats: &DispatchClassStats, p: &DispatchClassPercentiles| {
+			BTreeMap::from([
+				(
+					Statistic::Mean,
+					(stats.normal.mean, stats.operational.mean, stats.mandatory.mean).into(),
+				),
+				(
+					Statistic::Min,
+					(stats.normal.min, stats.operational.min, stats.mandatory.min).into(),
+				),
+				(
+					Statistic::Max,
+					(stats.normal.max, stats.operational.max, stats.mandatory.max).into(),
+				),
+				(Statistic::P50, (p.normal.p50, p.operational.p50, p.mandatory.p50).into()),
+				(Statistic::P90, (p.normal.p90, p.operational.p90, p.mandatory.p90).into()),
+				(Statistic::P99, (p.normal.p99, p.operational.p99, p.mandatory.p99).into()),
+			])
+		};
+
+		AggregatedData {
+			group: format_timestamp(rollup.period_start, Timezone::utc(), DAY_FORMAT),
+			ref_time: rollup.ref_time_sum,
+			proof_size: rollup.proof_size_sum,
+			count: rollup.count,
+			first_block: rollup.first_block,
+			last_block: rollup.last_block,
+			ref_time_stats: stats(&rollup.ref_time, &rollup.ref_time_percentiles),
+			proof_size_stats: stats(&rollup.proof_size, &rollup.proof_size_percentiles),
+		}
+	}
+}
+
+/// The names of the columns of the grouped consumption in a CSV format.
+pub fn csv_header(stats: &[Statistic]) -> Vec<String> {
+	let mut header: Vec<String> =
+		["group", "first_block", "last_block", "count"].map(String::from).to_vec();
+	let classes = ["normal", "operational", "mandatory"];
+
+	["ref_time", "proof_size"].into_iter().for_each(|resource| {
+		classes.iter().for_each(|class| header.push(format!("{}_{}", resource, class)));
+	});
+	["ref_time", "proof_size"].into_iter().for_each(|resource| {
+		stats.iter().for_each(|stat| {
+			classes
+				.iter()
+				.for_each(|class| header.push(format!("{}_{}_{}", resource, stat.name(), class)));
+		})
+	});
+
+	header
+}
+
+/// Encodes a CSV record, followed by a line terminator.
+fn csv_record(record: Vec<String>) -> String {
+	// Writing the record to memory can't fail.
+	let content = csv_content(vec![record]).unwrap_or_default();
+	String::from_utf8(content).unwrap_or_default()
+}
+
+/// The format in which the consumption is returned.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum Format {
+	/// A JSON object containing a page of groups.
+	Json,
+	/// A JSON encoded group per line.
+	Ndjson,
+	/// CSV with a header row.
+	Csv,
+}
+
+impl Format {
+	/// Determines the format based on the preferred media type of the `Accept` header.
+	fn negotiate(accept: Option<&Accept>) -> Self {
+		match accept.map(|accept| accept.preferred().media_type()) {
+			Some(media_type) if media_type.is_csv() => Format::Csv,
+			Some(media_type)
+				if media_type.top() == "application" && media_type.sub() == "x-ndjson" =>
+				Format::Ndjson,
+			_ => Format::Json,
+		}
+	}
+
+	fn content_type(&self) -> ContentType {
+		match self {
+			Format::Json => ContentType::JSON,
+			Format::Ndjson => ContentType::new("application", "x-ndjson"),
+			Format::Csv => ContentType::CSV,
+		}
+	}
 }
 
+impl FromStr for Format {
+	type Err = ();
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"json" => Ok(Format::Json),
+			"ndjson" => Ok(Format::Ndjson),
+			"csv" => Ok(Format::Csv),
+			_ => Err(()),
+		}
+	}
+}
+
+/// Applies the pagination to the groups as they are produced.
+struct Paginator {
+	cursor: Option<u32>,
+	/// The number of groups which remain to be skipped.
+	skip: usize,
+	page_size: usize,
+	/// The number of groups seen so far.
+	total: usize,
+	/// The number of groups included in the page so far.
+	included: usize,
+	/// The first block of the group following the page, once it is known.
+	next_cursor: Option<u32>,
+}
+
+impl Paginator {
+	/// Returns whether the group is part of the page.
+	fn include(&mut self, data: &AggregatedData) -> bool {
+		self.total += 1;
+
+		// The groups before the cursor belong to the previous pages.
+		if let Some(cursor) = self.cursor {
+			if data.first_block < cursor {
+				return false;
+			}
+		}
+
+		if self.skip > 0 {
+			self.skip -= 1;
+			return false;
+		}
+
+		if self.included < self.page_size {
+			self.included += 1;
+			return true;
+		}
+
+		self.next_cursor.get_or_insert(data.first_block);
+		false
+	}
+}
+
+/// A page of the grouped consumption.
+#[derive(Default, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ConsumptionPage {
+	pub items: Vec<AggregatedData>,
+	/// The number of groups within the requested range, over all the pages.
+	pub total: usize,
+	/// The `cursor` from which the next page starts, if there is one.
+	pub next_cursor: Option<u32>,
+}
+
+const DAY_FORMAT: &str = "%Y-%m-%d";
+
+/// The highest precision that can be requested. `f64` can't represent more decimals than this.
+const MAX_PRECISION: u32 = 15;
+
+/// The number of groups which can be read ahead of the streamed response.
+const GROUP_BUFFER: usize = 64;
+
 /// Query the consumption data of a parachain.
 ///
+/// The consumption is stored with full precision. `precision` can be used to round the returned
+/// values to a fixed number of decimals.
+///
+/// Along with the summed up consumption, the statistics of the blocks within each group are
+/// returned. `stats` can be used to select which of them to return, by default all of them are
+/// returned.
+///
+/// Instead of `grouping`, `bucket` can be used to group the data into buckets of a custom size.
+/// The time based groups follow the `tz` timezone, given either as an IANA zone name, e.g.
+/// `Europe/Berlin`, or as an offset from UTC, e.g. `-05:00` or `%2B02:00`. The offset of a zone is
+/// the one in effect at the time of each block. A `+` which isn't URL-encoded is decoded as a
+/// space, so the offset is rejected.
+///
+/// The data can be filtered by the time of the blocks, with `start` and `end` given either as
+/// timestamps in milliseconds or as ISO-8601 dates, and by their number through `from_block` and
+/// `to_block`. All the bounds are inclusive.
+///
+/// The pagination is applied to the groups. The response contains the total number of groups and
+/// the cursor of the next page, which is the first block of its first group. `page` can be used
+/// instead of the cursor to skip a number of pages.
+///
+/// The data is returned as JSON by default. It can also be exported as CSV or newline delimited
+/// JSON, either through the `format` parameter or the `Accept` header. Only the JSON response
+/// contains the pagination metadata. The groups are serialized one at a time while the response is
+/// streamed.
+///
 /// This will return an error in case there is no data associated with the specific parachain.
-#[get("/consumption/<relay>/<para_id>?<start>&<end>&<page>&<page_size>&<grouping>")]
-pub fn consumption(
+#[get(
+	"/consumption/<relay>/<para_id>?<start>&<end>&<from_block>&<to_block>&<cursor>&<page>&\
+	 <page_size>&<grouping>&<bucket>&<tz>&<precision>&<stats>&<format>"
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn consumption(
 	relay: &str,
 	para_id: ParaId,
-	start: Option<Timestamp>,
-	end: Option<Timestamp>,
-	page: Option<u32>,
-	page_size: Option<u32>,
-	grouping: Option<Grouping>,
-) -> Result<String, Error> {
-	let para = registered_para(relay.into(), para_id).ok_or(Error::NotRegistered)?;
+	start: Option<&str>,
+	end: Option<&str>,
+	from_block: Option<&str>,
+	to_block: Option<&str>,
+	cursor: Option<&str>,
+	page: Option<&str>,
+	page_size: Option<&str>,
+	grouping: Option<&str>,
+	bucket: Option<&str>,
+	tz: Option<&str>,
+	precision: Option<&str>,
+	stats: Option<&str>,
+	format: Option<&str>,
+	accept: Option<&Accept>,
+) -> Result<(ContentType, TextStream![String]), Error> {
+	let para = registered_para(relay_chain(relay)?, para_id)?.ok_or(Error::NotRegistered)?;
+
+	let from_block: Option<u32> = parse_param("from_block", from_block)?;
+	let to_block: Option<u32> = parse_param("to_block", to_block)?;
+	let cursor: Option<u32> = parse_param("cursor", cursor)?;
+	let page: Option<u32> = parse_param("page", page)?;
+	let page_size: Option<u32> = parse_param("page_size", page_size)?;
+	let grouping: Option<Grouping> = parse_param("grouping", grouping)?;
+	let bucket: Option<Bucket> = parse_param("bucket", bucket)?;
+	let precision: Option<u32> = parse_param("precision", precision)?;
+	let stats: Option<Statistics> = parse_param("stats", stats)?;
+	let format: Option<Format> = parse_param("format", format)?;
 
+	if page_size == Some(0) {
+		return Err(Error::InvalidPageSize);
+	}
 	let (page, page_size) = (page.unwrap_or_default(), page_size.unwrap_or(u32::MAX));
-	let (start, end) = (start.unwrap_or_default(), end.unwrap_or(Timestamp::MAX));
+	let start = start
+		.map(|start| parse_timestamp(start, false))
+		.transpose()?
+		.unwrap_or_default();
+	let end = end.map(|end| parse_timestamp(end, true)).transpose()?.unwrap_or(Timestamp::MAX);
+	let (from_block, to_block) = (from_block.unwrap_or_default(), to_block.unwrap_or(u32::MAX));
+	if start > end || from_block > to_block {
+		return Err(Error::InvalidRange);
+	}
+	let block_filtered = from_block != 0 || to_block != u32::MAX;
 
-	// By default query the consumption that was collected from rpc index 0.
-	let weight_consumptions: Vec<WeightConsumption> = get_consumption(para, None)
-		.map_err(|_| Error::ConsumptionDataNotFound)?
-		.into_iter()
-		.filter(|consumption| consumption.timestamp >= start && consumption.timestamp <= end)
-		.skip(page.saturating_mul(page_size) as usize)
-		.take(page_size as usize)
-		.collect();
+	let grouping = bucket.map(Grouping::Bucket).or(grouping).unwrap_or(Grouping::BlockNumber);
+	let tz = tz
+		.map(|tz| tz.parse().map_err(|_| Error::InvalidTimezone))
+		.transpose()?
+		.unwrap_or_else(Timezone::utc);
+
+	let format = format.unwrap_or_else(|| Format::negotiate(accept));
+	let stats = stats.map(|Statistics(stats)| stats).unwrap_or(Statistic::ALL.to_vec());
+
+	// The consumption is read and grouped by a blocking task, which sends the groups one at a
+	// time while the response is streamed. An error can only be sent before the first group.
+	let (sender, mut receiver) = mpsc::channel::<Result<AggregatedData, Error>>(GROUP_BUFFER);
+	let group_stats = stats.clone();
+	spawn_blocking(move || {
+		// The daily aggregates are precomputed by the processor so there is no need to go over
+		// all the blocks.
+		//
+		// The rollups are made of UTC days, so they can't be used for the days of other
+		// timezones, nor when filtering by block number.
+		let groups: Box<dyn Iterator<Item = AggregatedData>> =
+			if grouping == Grouping::Day && tz == Timezone::utc() && !block_filtered {
+				match daily_consumption(para, start, end, &group_stats) {
+					Ok(groups) => Box::new(groups.into_iter()),
+					Err(e) => return drop(sender.blocking_send(Err(e))),
+				}
+			} else {
+				// By default query the consumption that was collected from rpc index 0.
+				//
+				// The consumption is read lazily, relying on it being stored in the order of the
+				// block numbers.
+				match read_consumption_between(para, None, start, end) {
+					Ok(consumption) => Box::new(lazy_groups(
+						consumption
+							.filter(|datum| (from_block..=to_block).contains(&datum.block_number)),
+						grouping,
+						tz,
+						&group_stats,
+					)),
+					Err(_) =>
+						return drop(sender.blocking_send(Err(Error::ConsumptionDataNotFound))),
+				}
+			};
+
+		for data in groups {
+			// The receiver is dropped once the client disconnected.
+			if sender.blocking_send(Ok(data)).is_err() {
+				return;
+			}
+		}
+	});
+
+	let mut first = match receiver.recv().await {
+		Some(Err(e)) => return Err(e),
+		first => first.and_then(Result::ok),
+	};
+	let precision = precision.map(|precision| precision.min(MAX_PRECISION));
+	let mut paginator = Paginator {
+		cursor,
+		skip: page.saturating_mul(page_size) as usize,
+		page_size: page_size as usize,
+		total: 0,
+		included: 0,
+		next_cursor: None,
+	};
+
+	let stream = TextStream! {
+		match format {
+			Format::Json => yield r#"{"items":["#.to_string(),
+			Format::Csv => yield csv_record(csv_header(&stats)),
+			Format::Ndjson => {},
+		}
+
+		loop {
+			let mut data = match first.take() {
+				Some(data) => data,
+				None => match receiver.recv().await {
+					Some(Ok(data)) => data,
+					_ => break,
+				},
+			};
+			if !paginator.include(&data) {
+				continue;
+			}
+
+			data.retain_stats(&stats);
+			if let Some(precision) = precision {
+				data.round(precision);
+			}
+
+			// Serializing the aggregated data can't fail.
+			let json = || serde_json::to_string(&data).unwrap_or_default();
+			yield match format {
+				Format::Json if paginator.included > 1 => format!(",{}", json()),
+				Format::Json => json(),
+				Format::Ndjson => json() + "\n",
+				Format::Csv => csv_record(data.to_csv(&stats)),
+			};
+		}
+
+		if format == Format::Json {
+			yield format!(
+				r#"],"total":{},"next_cursor":{}}}"#,
+				paginator.total,
+				serde_json::to_string(&paginator.next_cursor).unwrap_or_default()
+			);
+		}
+	};
+
+	Ok((format.content_type(), stream))
+}
 
-	let grouping = grouping.unwrap_or(Grouping::BlockNumber);
+/// Parses an optional query parameter.
+///
+/// Rocket ignores optional parameters which fail to parse, so they are taken as strings and parsed
+/// here instead, rejecting invalid values rather than silently falling back to the default.
+fn parse_param<T: FromStr>(name: &str, value: Option<&str>) -> Result<Option<T>, Error> {
+	value
+		.map(|value| value.parse().map_err(|_| Error::InvalidParameter(name.into())))
+		.transpose()
+}
 
-	let grouped = group_consumption(weight_consumptions, grouping);
+/// Parses a timestamp in milliseconds or an ISO-8601 date, e.g. `2024-03-01`,
+/// `2024-03-01T12:00:00` or `2024-03-01T12:00:00+02:00`. The dates without an offset are in UTC.
+///
+/// A date without a time refers to the start of the day, or to its end if `end_of_day` is set.
+fn parse_timestamp(value: &str, end_of_day: bool) -> Result<Timestamp, Error> {
+	if let Ok(timestamp) = value.parse::<Timestamp>() {
+		return Ok(timestamp);
+	}
+
+	let datetime = if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+		datetime.naive_utc()
+	} else if let Ok(datetime) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f") {
+		datetime
+	} else {
+		let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| Error::InvalidTime)?;
+		match end_of_day {
+			true => date.and_hms_milli_opt(23, 59, 59, 999),
+			false => date.and_hms_opt(0, 0, 0),
+		}
+		.ok_or(Error::InvalidTime)?
+	};
 
-	serde_json::to_string(&grouped).map_err(|_| Error::InvalidData)
+	Timestamp::try_from(datetime.and_utc().timestamp_millis()).map_err(|_| Error::InvalidTime)
 }
 
 pub fn group_consumption(
 	weight_consumptions: Vec<WeightConsumption>,
 	grouping: Grouping,
 ) -> Vec<AggregatedData> {
-	let grouped = weight_consumptions.iter().fold(BTreeMap::new(), |mut acc, datum| {
-		let key = get_aggregation_key(datum.clone(), grouping);
-		let entry: &mut AggregatedData = acc.entry(key).or_default();
+	group_consumption_in(weight_consumptions, grouping, Timezone::utc())
+}
 
-		entry.ref_time.normal += datum.ref_time.normal;
-		entry.ref_time.operational += datum.ref_time.operational;
-		entry.ref_time.mandatory += datum.ref_time.mandatory;
+/// Groups the consumption, with the time based groups following the given timezone.
+///
+/// The groups are ordered by their first block.
+pub fn group_consumption_in(
+	mut weight_consumptions: Vec<WeightConsumption>,
+	grouping: Grouping,
+	tz: Timezone,
+) -> Vec<AggregatedData> {
+	weight_consumptions.sort_by_key(|datum| datum.block_number);
+	lazy_groups(weight_consumptions.into_iter(), grouping, tz, &Statistic::ALL).collect()
+}
 
-		entry.proof_size.normal += datum.proof_size.normal;
-		entry.proof_size.operational += datum.proof_size.operational;
-		entry.proof_size.mandatory += datum.proof_size.mandatory;
+/// Groups the consumption one group at a time, computing the given statistics.
+///
+/// The consumption has to be ordered by block number. Since the block timestamps increase along
+/// with the block numbers, the blocks of a group are always adjacent.
+fn lazy_groups(
+	weight_consumptions: impl Iterator<Item = WeightConsumption>,
+	grouping: Grouping,
+	tz: Timezone,
+	stats: &[Statistic],
+) -> impl Iterator<Item = AggregatedData> {
+	let mut consumption = weight_consumptions.peekable();
+	let stats = stats.to_vec();
 
-		entry.count += 1;
+	std::iter::from_fn(move || {
+		let first = consumption.next()?;
+		let group = get_aggregation_key(&first, grouping, tz);
 
-		acc
-	});
+		let mut accumulator = Accumulator::new(group.clone(), &stats);
+		accumulator.add(&first);
+		while let Some(datum) =
+			consumption.next_if(|datum| get_aggregation_key(datum, grouping, tz) == group)
+		{
+			accumulator.add(&datum);
+		}
+
+		Some(accumulator.finish(&stats))
+	})
+}
+
+/// Aggregates the consumption of the blocks within a group as they are read.
+///
+/// The consumption of the individual blocks is only kept for the percentiles, if any of them is
+/// requested.
+struct Accumulator {
+	data: AggregatedData,
+	ref_time: [ClassStats; 3],
+	proof_size: [ClassStats; 3],
+}
+
+impl Accumulator {
+	fn new(group: String, stats: &[Statistic]) -> Self {
+		let keep_values = stats.iter().any(|stat| stat.percentile().is_some());
+		let class_stats = || std::array::from_fn(|_| ClassStats::new(keep_values));
+
+		Accumulator {
+			data: AggregatedData { group, ..Default::default() },
+			ref_time: class_stats(),
+			proof_size: class_stats(),
+		}
+	}
 
-	grouped
+	fn add(&mut self, datum: &WeightConsumption) {
+		let data = &mut self.data;
+		if data.count == 0 {
+			data.first_block = datum.block_number;
+		}
+		data.count += 1;
+		data.last_block = datum.block_number;
+
+		for (sum, stats, consumption) in [
+			(&mut data.ref_time, &mut self.ref_time, &datum.ref_time),
+			(&mut data.proof_size, &mut self.proof_size, &datum.proof_size),
+		] {
+			sum.normal += consumption.normal;
+			sum.operational += consumption.operational;
+			sum.mandatory += consumption.mandatory;
+
+			stats[0].add(consumption.normal);
+			stats[1].add(consumption.operational);
+			stats[2].add(consumption.mandatory);
+		}
+	}
+
+	fn finish(mut self, stats: &[Statistic]) -> AggregatedData {
+		let count = self.data.count;
+		let statistics = |sum: &DispatchClassConsumption, classes: &mut [ClassStats; 3]| {
+			classes.iter_mut().for_each(ClassStats::sort);
+			stats
+				.iter()
+				.map(|&stat| {
+					let consumption = (
+						classes[0].compute(stat, sum.normal, count),
+						classes[1].compute(stat, sum.operational, count),
+						classes[2].compute(stat, sum.mandatory, count),
+					);
+					(stat, consumption.into())
+				})
+				.collect()
+		};
+
+		self.data.ref_time_stats = statistics(&self.data.ref_time, &mut self.ref_time);
+		self.data.proof_size_stats = statistics(&self.data.proof_size, &mut self.proof_size);
+		self.data
+	}
+}
+
+/// The statistics of the consumption of a single dispatch class within a group.
+struct ClassStats {
+	min: f64,
+	max: f64,
+	/// The consumption of each block, only kept for computing the percentiles.
+	values: Option<Vec<f64>>,
+}
+
+impl ClassStats {
+	fn new(keep_values: bool) -> Self {
+		ClassStats {
+			min: f64::INFINITY,
+			max: f64::NEG_INFINITY,
+			values: keep_values.then(Vec::new),
+		}
+	}
+
+	fn add(&mut self, value: f64) {
+		self.min = self.min.min(value);
+		self.max = self.max.max(value);
+		if let Some(values) = &mut self.values {
+			values.push(value);
+		}
+	}
+
+	fn sort(&mut self) {
+		if let Some(values) = &mut self.values {
+			values.sort_by(f64::total_cmp);
+		}
+	}
+
+	/// Computes the statistic, once the values are sorted.
+	fn compute(&self, stat: Statistic, sum: f64, count: usize) -> f64 {
+		if count == 0 {
+			return 0.0;
+		}
+
+		match (stat, stat.percentile(), &self.values) {
+			(Statistic::Mean, ..) => sum / count as f64,
+			(Statistic::Min, ..) => self.min,
+			(Statistic::Max, ..) => self.max,
+			(_, Some(p), Some(values)) => percentile(values, p),
+			// The values aren't kept unless a percentile is requested.
+			_ => 0.0,
+		}
+	}
+}
+
+/// Returns the daily aggregated consumption of a parachain.
+///
+/// The days which were already rolled up are read from the rollups, so only the per-block data
+/// starting from the last rolled up day is read and aggregated on the fly. That day is aggregated
+/// from the per-block data since it might have been rolled up before it was over.
+///
+/// Only the days entirely within the `start` and `end` timestamps are read from the rollups. The
+/// days which are cut by the bounds are aggregated from the per-block data within the bounds, the
+/// same as when the data isn't grouped by day.
+fn daily_consumption(
+	para: Parachain,
+	start: Timestamp,
+	end: Timestamp,
+	stats: &[Statistic],
+) -> Result<Vec<AggregatedData>, Error> {
+	let day = RollupPeriod::Day;
+	let day_end =
+		|timestamp: Timestamp| day.period_start(timestamp).saturating_add(day.duration() - 1);
+
+	let rollups = get_rollups(para.clone(), day).unwrap_or_default();
+	let pending_start = rollups.iter().map(|rollup| rollup.period_start).max().unwrap_or_default();
+
+	let mut grouped: BTreeMap<String, AggregatedData> = rollups
 		.into_iter()
-		.map(|(key, entry)| {
-			let mut entry = entry;
-			entry.group = key;
-			entry
+		.filter(|rollup| rollup.period_start >= start && day_end(rollup.period_start) <= end)
+		.map(|rollup| {
+			let data = AggregatedData::from_daily_rollup(rollup);
+			(data.group.clone(), data)
 		})
-		.collect()
-}
+		.collect();
+
+	// The ranges of the per-block data to read. The partial days after the last rolled up day are
+	// covered by the last range.
+	let (first_day, last_day) = (day.period_start(start), day.period_start(end));
+	let mut ranges = vec![];
+	if (start != first_day || end < day_end(start)) && first_day < pending_start {
+		ranges.push((start, end.min(day_end(start))));
+	}
+	if last_day != first_day && end != day_end(end) && last_day < pending_start {
+		ranges.push((last_day, end));
+	}
+	ranges.push((start.max(pending_start), end));
+
+	let mut found = !grouped.is_empty();
+	for (from, to) in ranges.into_iter().filter(|(from, to)| from <= to) {
+		// The per-block data might have been pruned already.
+		let Ok(weight_consumptions) = read_consumption_between(para.clone(), None, from, to) else {
+			continue;
+		};
+		found = true;
 
-fn get_aggregation_key(datum: WeightConsumption, grouping: Grouping) -> String {
-	let datetime =
-		NaiveDateTime::from_timestamp_opt((datum.timestamp / 1000) as i64, 0).unwrap_or_default();
+		lazy_groups(weight_consumptions, Grouping::Day, Timezone::utc(), stats).for_each(|data| {
+			grouped.insert(data.group.clone(), data);
+		});
+	}
+
+	if !found {
+		return Err(Error::ConsumptionDataNotFound);
+	}
 
+	Ok(grouped.into_values().collect())
+}
+
+fn get_aggregation_key(datum: &WeightConsumption, grouping: Grouping, tz: Timezone) -> String {
 	match grouping {
 		Grouping::BlockNumber => datum.block_number.to_string(),
-		Grouping::Minute => datetime.format("%Y-%m-%dT%H:%M").to_string(),
-		Grouping::Hour => datetime.format("%Y-%m-%dT%H:00").to_string(),
-		Grouping::Day => datetime.format("%Y-%m-%d").to_string(),
-		Grouping::Month => datetime.format("%Y-%m").to_string(),
-		Grouping::Year => datetime.format("%Y").to_string(),
+		Grouping::Minute => format_timestamp(datum.timestamp, tz, "%Y-%m-%dT%H:%M"),
+		Grouping::Hour => format_timestamp(datum.timestamp, tz, "%Y-%m-%dT%H:00"),
+		Grouping::Day => format_timestamp(datum.timestamp, tz, DAY_FORMAT),
+		Grouping::Week => format_timestamp(datum.timestamp, tz, "%G-W%V"),
+		Grouping::Month => format_timestamp(datum.timestamp, tz, "%Y-%m"),
+		Grouping::Quarter => {
+			let datetime = local_datetime(datum.timestamp, tz);
+			format!("{}-Q{}", datetime.year(), datetime.month0() / 3 + 1)
+		},
+		Grouping::Year => format_timestamp(datum.timestamp, tz, "%Y"),
+		Grouping::Bucket(Bucket::Interval(interval)) => {
+			// The buckets are aligned to the local time, using the offset in effect at the time of
+			// the block.
+			let local = local_datetime(datum.timestamp, tz).and_utc().timestamp_millis();
+			let bucket_start = local - local.rem_euclid(interval as i64);
+			utc_datetime(bucket_start).format("%Y-%m-%dT%H:%M").to_string()
+		},
+		Grouping::Bucket(Bucket::Blocks(size)) => {
+			let first = datum.block_number - datum.block_number % size;
+			format!("{}-{}", first, first.saturating_add(size - 1))
+		},
 	}
 }
+
+/// Returns the date and time of the timestamp, given in milliseconds, in UTC.
+fn utc_datetime(timestamp: i64) -> NaiveDateTime {
+	NaiveDateTime::from_timestamp_opt(timestamp.div_euclid(1000), 0).unwrap_or_default()
+}
+
+/// Returns the date and time of the timestamp in the given timezone.
+fn local_datetime(timestamp: Timestamp, tz: Timezone) -> NaiveDateTime {
+	let datetime = utc_datetime(timestamp as i64);
+
+	datetime + tz.offset_at(&datetime)
+}
+
+fn format_timestamp(timestamp: Timestamp, tz: Timezone, format: &str) -> String {
+	local_datetime(timestamp, tz).format(format).to_string()
+}
@@ -14,14 +14,16 @@
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::*;
-use polkadot_core_primitives::BlockNumber;
 use rocket::{post, serde::json::Json};
 use shared::{
+	audit::ChangeOrigin,
 	config::config,
 	current_timestamp,
+	ledger::{PaymentReceipt, PaymentReference},
 	payment::validate_registration_payment,
-	registry::{registered_para, registered_paras, update_registry},
+	registry::{modify_registry_with_payment, registered_para},
 };
+use std::net::IpAddr;
 use types::{ParaId, RelayChain};
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -29,13 +31,18 @@ use types::{ParaId, RelayChain};
 pub struct ExtendSubscriptionData {
 	/// The parachain which is getting its subscription extended.
 	pub para: (RelayChain, ParaId),
-	/// The block in which the payment occurred for the specific parachain.
-	pub payment_block_number: BlockNumber,
+	/// The payment for the specific parachain.
+	pub payment: PaymentReference,
 }
 
 /// Extend the subscription of a parachain for resource utilization tracking.
+///
+/// Returns the receipt of the payment, or `null` if payments are disabled.
 #[post("/extend-subscription", data = "<data>")]
-pub async fn extend_subscription(data: Json<ExtendSubscriptionData>) -> Result<(), Error> {
+pub async fn extend_subscription(
+	data: Json<ExtendSubscriptionData>,
+	ip: Option<IpAddr>,
+) -> Result<Json<Option<PaymentReceipt>>, Error> {
 	let (relay_chain, para_id) = data.para.clone();
 
 	log::info!(
@@ -44,36 +51,44 @@ pub async fn extend_subscription(data: Json<ExtendSubscriptionData>) -> Result<(
 		relay_chain, para_id
 	);
 
-	let para = registered_para(relay_chain.clone(), para_id).ok_or(Error::NotRegistered)?;
+	let para = registered_para(relay_chain.clone(), para_id)?.ok_or(Error::NotRegistered)?;
 
-	let subscription_duration = if let Some(payment_info) = config().payment_info {
+	let mut origin = ChangeOrigin { actor: caller(ip), payment_block_number: None };
+	let mut receipt = None;
+
+	let subscription = if let Some(payment_info) = config().payment_info_for(&relay_chain).cloned()
+	{
 		if para.expiry_timestamp.saturating_sub(payment_info.renewal_period) > current_timestamp() {
 			// Cannot renew yet.
 			return Err(Error::AlreadyRegistered);
 		}
 
-		validate_registration_payment(
-			para.clone(),
-			payment_info.clone(),
-			data.payment_block_number,
-		)
-		.await
-		.map_err(Error::PaymentValidationError)?;
+		let validated =
+			validate_registration_payment(para.clone(), payment_info.clone(), data.payment.clone())
+				.await
+				.map_err(Error::PaymentValidationError)?;
+
+		let subscription = paid_subscription(&payment_info, &validated)?;
+		receipt = Some(validated);
 
-		payment_info.subscription_duration
+		origin.payment_block_number = Some(data.payment.block_number());
+		Some(subscription)
 	} else {
-		Default::default()
+		None
 	};
 
-	let mut paras = registered_paras();
-
-	if let Some(para) = paras.iter_mut().find(|p| **p == para) {
-		para.expiry_timestamp += subscription_duration;
-	} else {
-		return Err(Error::NotRegistered);
-	}
+	modify_registry_with_payment(&origin, |paras| -> Result<_, Error> {
+		let para = paras
+			.iter_mut()
+			.find(|p| p.relay_chain == relay_chain && p.para_id == para_id)
+			.ok_or(Error::NotRegistered)?;
 
-	if let Err(err) = update_registry(paras) {
+		if let Some(subscription) = &subscription {
+			subscription.extend(para, current_timestamp());
+		}
+		Ok(((), receipt.clone().map(|receipt| (receipt, (relay_chain.clone(), para_id)))))
+	})
+	.map_err(|err| {
 		log::error!(
 			target: LOG_TARGET,
 			"{}-{} Failed to extend subscription for para: {:?}",
@@ -81,10 +96,11 @@ pub async fn extend_subscription(data: Json<ExtendSubscriptionData>) -> Result<(
 			para.para_id,
 			err
 		);
-	} else {
-		#[cfg(not(debug_assertions))]
-		shared::init_tracker();
-	}
+		err
+	})?;
+
+	#[cfg(not(debug_assertions))]
+	shared::init_tracker();
 
-	Ok(())
+	Ok(Json(receipt))
 }
@@ -20,10 +20,23 @@
 //! - `/register`: Used to register a parachain for consumption tracking.
 //! - `/registry`: Used for querying all the registered parachains.
 //! - `/extend-subscription`: For extending the subscription of a parachain.
+//! - `/subscription`: For querying the subscription status and the price of a parachain.
 
-use rocket::{http::Status, response::Responder, Request, Response};
+use rocket::{
+	http::{ContentType, Status},
+	response::Responder,
+	Catcher, Request, Response,
+};
 use serde::{Deserialize, Serialize};
-use shared::{chaindata::ChainDataError, payment::PaymentError};
+use shared::{
+	chaindata::ChainDataError,
+	config::{PaymentInfo, Subscription},
+	ledger::PaymentReceipt,
+	payment::PaymentError,
+	registry::RegistryError,
+};
+use std::net::IpAddr;
+use types::RelayChain;
 
 const LOG_TARGET: &str = "server";
 
@@ -46,31 +59,189 @@ pub enum Error {
 	ChainDataError(ChainDataError),
 	/// An error occured when trying to validate the payment.
 	PaymentValidationError(PaymentError),
+	/// An error occured when trying to access the registry.
+	RegistryError(RegistryError),
+	/// The caller isn't authorized to use the route.
+	Unauthorized,
+	/// The provided time couldn't be parsed.
+	InvalidTime,
+	/// The provided timezone is neither a zone name nor an offset from UTC.
+	InvalidTimezone,
+	/// The start of the requested range is after its end.
+	InvalidRange,
+	/// The requested page size is zero.
+	InvalidPageSize,
+	/// The value of the named query parameter is invalid.
+	InvalidParameter(String),
+	/// No route matches the requested path.
+	NotFound,
+	/// The request doesn't have the expected form, e.g. its body isn't valid JSON or a path
+	/// parameter has the wrong type.
+	InvalidRequest,
+}
+
+/// The body of an error response.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ErrorResponse {
+	/// The name of the error variant, e.g. `NotRegistered`.
+	pub code: String,
+	/// A human readable description of the error.
+	pub message: String,
+	/// The nested error, if any, e.g. `"NotFound"` for a `PaymentValidationError`.
+	pub details: Option<serde_json::Value>,
+}
+
+impl Error {
+	/// The status code the error is returned with.
+	pub fn status(&self) -> Status {
+		match self {
+			Self::AlreadyRegistered => Status::Conflict,
+			Self::NotRegistered | Self::ConsumptionDataNotFound | Self::NotFound =>
+				Status::NotFound,
+			Self::PaymentRequired => Status::PaymentRequired,
+			Self::ChainDataError(ChainDataError::ParaNotFound) => Status::NotFound,
+			Self::PaymentValidationError(e) => match e {
+				PaymentError::NotFound | PaymentError::Failed => Status::PaymentRequired,
+				PaymentError::AlreadyUsed | PaymentError::Unfinalized => Status::Conflict,
+				PaymentError::ValidationFailed => Status::InternalServerError,
+			},
+			Self::Unauthorized => Status::Unauthorized,
+			Self::InvalidTime |
+			Self::InvalidTimezone |
+			Self::InvalidRange |
+			Self::InvalidPageSize |
+			Self::InvalidParameter(_) => Status::BadRequest,
+			Self::InvalidRequest => Status::UnprocessableEntity,
+			Self::InvalidData | Self::RegistryError(_) => Status::InternalServerError,
+		}
+	}
+
+	fn message(&self) -> &'static str {
+		match self {
+			Self::AlreadyRegistered => "The parachain is already registered.",
+			Self::NotRegistered => "The parachain is not registered.",
+			Self::ConsumptionDataNotFound => "No consumption data was found for the parachain.",
+			Self::InvalidData => "The stored data is invalid.",
+			Self::PaymentRequired => "A payment is required.",
+			Self::ChainDataError(_) => "Failed to resolve the parachain from the chaindata.",
+			Self::PaymentValidationError(_) => "Failed to validate the payment.",
+			Self::RegistryError(_) => "Failed to access the registry.",
+			Self::Unauthorized => "Not authorized to use the route.",
+			Self::InvalidTime => "The time is neither a timestamp nor an ISO-8601 date.",
+			Self::InvalidTimezone =>
+				"The timezone is neither an IANA zone name nor an offset from UTC, e.g. `-05:00`.",
+			Self::InvalidRange => "The start of the range is after its end.",
+			Self::InvalidPageSize => "The page size must be at least 1.",
+			Self::InvalidParameter(_) => "The value of a query parameter is invalid.",
+			Self::NotFound => "The requested resource does not exist.",
+			Self::InvalidRequest => "The request is malformed.",
+		}
+	}
+}
+
+impl From<&Error> for ErrorResponse {
+	fn from(error: &Error) -> Self {
+		// Unit variants are serialized as their name, while the variants containing an error are
+		// serialized as a map from their name to the nested error.
+		let (code, details) = match serde_json::to_value(error).unwrap_or_default() {
+			serde_json::Value::Object(map) => map
+				.into_iter()
+				.next()
+				.map(|(code, details)| (code, Some(details)))
+				.unwrap_or_default(),
+			serde_json::Value::String(code) => (code, None),
+			_ => Default::default(),
+		};
+
+		Self { code, message: error.message().into(), details }
+	}
+}
+
+impl TryFrom<ErrorResponse> for Error {
+	type Error = serde_json::Error;
+
+	fn try_from(response: ErrorResponse) -> Result<Self, Self::Error> {
+		let value = match response.details {
+			Some(details) =>
+				serde_json::Value::Object([(response.code, details)].into_iter().collect()),
+			None => serde_json::Value::String(response.code),
+		};
+
+		serde_json::from_value(value)
+	}
 }
 
 impl<'r> Responder<'r, 'static> for Error {
 	fn respond_to(self, _: &'r Request<'_>) -> Result<Response<'static>, Status> {
-		let body = format!("{:?}", self);
+		let body = serde_json::to_string(&ErrorResponse::from(&self))
+			.map_err(|_| Status::InternalServerError)?;
+
 		Response::build()
-			.status(Status::InternalServerError)
+			.status(self.status())
+			.header(ContentType::JSON)
 			.sized_body(body.len(), std::io::Cursor::new(body))
 			.ok()
 	}
 }
 
-impl From<String> for Error {
-	fn from(v: String) -> Self {
-		match v.as_str() {
-			"AlreadyRegistered" => Self::AlreadyRegistered,
-			"NotRegistered" => Self::NotRegistered,
-			"ConsumptionDataNotFound" => Self::ConsumptionDataNotFound,
-			"InvalidData" => Self::InvalidData,
-			_ => panic!("UnknownError"),
-		}
+/// Catchers returning a JSON error body for the requests which fail before reaching a route, e.g.
+/// because a request guard rejected them or no route matches them.
+pub fn catchers() -> Vec<Catcher> {
+	rocket::catchers![unauthorized, not_found, unprocessable_entity]
+}
+
+#[rocket::catch(401)]
+fn unauthorized() -> Error {
+	Error::Unauthorized
+}
+
+#[rocket::catch(404)]
+fn not_found() -> Error {
+	Error::NotFound
+}
+
+#[rocket::catch(422)]
+fn unprocessable_entity() -> Error {
+	Error::InvalidRequest
+}
+
+/// Parses the relay chain given in the path of a route.
+///
+/// No parachain can be registered on an unknown relay chain.
+fn relay_chain(relay: &str) -> Result<RelayChain, Error> {
+	relay.parse().map_err(|_| Error::NotRegistered)
+}
+
+/// Identifies the caller in the registry audit log.
+fn caller(ip: Option<IpAddr>) -> String {
+	ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".into())
+}
+
+/// Returns the subscription bought by a validated payment.
+fn paid_subscription(
+	payment_info: &PaymentInfo,
+	receipt: &PaymentReceipt,
+) -> Result<Subscription, Error> {
+	payment_info
+		.subscription(receipt.currency, receipt.amount)
+		.ok_or(Error::PaymentValidationError(PaymentError::ValidationFailed))
+}
+
+impl From<RegistryError> for Error {
+	fn from(e: RegistryError) -> Self {
+		Self::RegistryError(e)
+	}
+}
+
+impl From<PaymentError> for Error {
+	fn from(e: PaymentError) -> Self {
+		Self::PaymentValidationError(e)
 	}
 }
 
+pub mod admin;
 pub mod consumption;
 pub mod extend_subscription;
 pub mod register;
 pub mod registry;
+pub mod subscription;
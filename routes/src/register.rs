@@ -14,23 +14,102 @@
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::*;
-use polkadot_core_primitives::BlockNumber;
 use rocket::{post, serde::json::Json};
-use shared::registry::{registered_para, registered_paras, update_registry};
-use types::Parachain;
+use shared::{
+	audit::ChangeOrigin,
+	chaindata::get_para,
+	config::config,
+	current_timestamp,
+	ledger::{PaymentReceipt, PaymentReference},
+	payment::validate_registration_payment,
+	registry::{modify_registry_with_payment, registered_para},
+};
+use std::net::IpAddr;
+use types::{ParaId, RelayChain, Timestamp};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RegistrationData {
+	/// The parachain which is getting registered.
+	pub para: (RelayChain, ParaId),
+	/// The payment for the specific parachain.
+	///
+	/// Required unless payments are disabled.
+	pub payment: Option<PaymentReference>,
+}
 
 /// Register a parachain for resource utilization tracking.
-#[post("/register_para", data = "<para>")]
-pub fn register_para(para: Json<Parachain>) -> Result<(), Error> {
-	let mut paras = registered_paras();
-
-	if registered_para(relay_chain.clone(), para_id).is_some() {
+///
+/// The name and the rpcs of the parachain are resolved from the chaindata. If payments are
+/// enabled the registration requires a payment, which determines the initial expiry.
+///
+/// Returns the receipt of the payment, or `null` if no payment was required.
+#[post("/register_para", data = "<data>")]
+pub async fn register_para(
+	data: Json<RegistrationData>,
+	ip: Option<IpAddr>,
+) -> Result<Json<Option<PaymentReceipt>>, Error> {
+	let (relay_chain, para_id) = data.para.clone();
+
+	log::info!(
+		target: LOG_TARGET,
+		"{}-{} - Attempting to register para",
+		relay_chain, para_id
+	);
+
+	let mut para = get_para(relay_chain.clone(), para_id).map_err(Error::ChainDataError)?;
+
+	// Check this before validating the payment to avoid needless rpc calls.
+	if registered_para(relay_chain, para_id)?.is_some() {
 		return Err(Error::AlreadyRegistered);
 	}
 
-	paras.push(para.into_inner());
-
-	if let Err(err) = update_registry(paras) {
+	let mut origin = ChangeOrigin { actor: caller(ip), payment_block_number: None };
+	let mut receipt = None;
+
+	let config = config();
+	para.expiry_timestamp = match config.payment_info_for(&para.relay_chain).cloned() {
+		Some(payment_info) => {
+			let subscription = if config.free_mode {
+				payment_info.single_period()
+			} else {
+				let payment = data.payment.clone().ok_or(Error::PaymentRequired)?;
+				origin.payment_block_number = Some(payment.block_number());
+
+				let validated =
+					validate_registration_payment(para.clone(), payment_info.clone(), payment)
+						.await
+						.map_err(Error::PaymentValidationError)?;
+
+				let subscription = paid_subscription(&payment_info, &validated)?;
+				receipt = Some(validated);
+				subscription
+			};
+
+			para.plan = subscription.plan;
+			current_timestamp().saturating_add(subscription.duration)
+		},
+		// Without payments the subscription never expires.
+		None => Timestamp::MAX,
+	};
+
+	modify_registry_with_payment(&origin, |paras| -> Result<_, Error> {
+		if paras
+			.iter()
+			.any(|p| p.relay_chain == para.relay_chain && p.para_id == para.para_id)
+		{
+			return Err(Error::AlreadyRegistered);
+		}
+
+		paras.push(para.clone());
+		Ok((
+			(),
+			receipt
+				.clone()
+				.map(|receipt| (receipt, (para.relay_chain.clone(), para.para_id))),
+		))
+	})
+	.map_err(|err| {
 		log::error!(
 			target: LOG_TARGET,
 			"{}-{} - Failed to register para: {:?}",
@@ -38,119 +117,11 @@ pub fn register_para(para: Json<Parachain>) -> Result<(), Error> {
 			para.para_id,
 			err
 		);
-	} else {
-		#[cfg(not(debug_assertions))]
-		shared::init_tracker();
-	}
-
-	Ok(())
-}
-
-async fn validate_registration_payment(
-	para: Parachain,
-	payment_info: PaymentInfo,
-	payment_block_number: BlockNumber,
-) -> Result<(), Error> {
-	// TODO: Could this code be improved so that we don't have to instantiate both clients?
-	let rpc_client = RpcClient::from_url(&payment_info.rpc_url.clone())
-		.await
-		.map_err(|_| Error::PaymentValidationFailed)?;
-
-	let online_client = OnlineClient::<PolkadotConfig>::from_url(payment_info.rpc_url.clone())
-		.await
-		.map_err(|_| Error::PaymentValidationFailed)?;
-
-	// Ensure that the `payment_block_number` is from a finalized block.
-	let last_finalized =
-		get_last_finalized_block(rpc_client.clone(), online_client.clone()).await?;
-	if payment_block_number > last_finalized {
-		return Err(Error::UnfinalizedPayment)
-	}
-
-	let block_hash = get_block_hash(rpc_client, payment_block_number).await?;
-	let block = get_block(online_client, block_hash).await?;
-
-	ensure_contains_payment(para, payment_info, block).await
-}
-
-async fn ensure_contains_payment(
-	para: Parachain,
-	payment_info: PaymentInfo,
-	block: Block<PolkadotConfig, OnlineClient<PolkadotConfig>>,
-) -> Result<(), Error> {
-	let payment = opaque_payment_extrinsic(para, payment_info).await?;
-
-	let extrinsics = block.extrinsics().await.map_err(|_| Error::PaymentValidationFailed)?;
-	let extrinsics: Vec<Vec<u8>> = extrinsics
-		.iter()
-		.filter_map(|ext| {
-			ext.as_ref().ok().and_then(|e| e.as_root_extrinsic::<polkadot::Call>().ok())
-		})
-		.map(|ext| ext.encode())
-		.collect();
-
-	if extrinsics.contains(&payment.encode()) {
-		Ok(())
-	} else {
-		Err(Error::PaymentNotFound)
-	}
-}
-
-async fn opaque_payment_extrinsic(
-	para: Parachain,
-	payment_info: PaymentInfo,
-) -> Result<polkadot::Call, Error> {
-	if let Ok(cost) = payment_info.cost.parse::<u128>() {
-		let transfer_call = polkadot::Call::Balances(BalancesCall::transfer_keep_alive {
-			dest: payment_info.receiver.into(),
-			value: cost,
-		});
-
-		let remark = format!("{}:{}", para.relay_chain, para.para_id).as_bytes().to_vec();
-		let remark_call = polkadot::Call::System(SystemCall::remark { remark });
-
-		let batch_call = polkadot::Call::Utility(UtilityCall::batch_all {
-			calls: vec![transfer_call, remark_call],
-		});
-
-		Ok(batch_call)
-	} else {
-		log::error!(
-			target: LOG_TARGET,
-			"Failed to parse cost",
-		);
-		Err(Error::PaymentValidationFailed)
-	}
-}
-
-async fn get_last_finalized_block(
-	rpc_client: RpcClient,
-	online_client: OnlineClient<PolkadotConfig>,
-) -> Result<BlockNumber, Error> {
-	let params = rpc_params![];
-	let block_hash: H256 = rpc_client
-		.request("chain_getFinalizedHead", params)
-		.await
-		.map_err(|_| Error::PaymentValidationFailed)?;
-
-	let block = get_block(online_client, block_hash).await?;
-
-	Ok(block.number())
-}
-
-async fn get_block(
-	api: OnlineClient<PolkadotConfig>,
-	block_hash: H256,
-) -> Result<Block<PolkadotConfig, OnlineClient<PolkadotConfig>>, Error> {
-	api.blocks().at(block_hash).await.map_err(|_| Error::PaymentValidationFailed)
-}
+		err
+	})?;
 
-async fn get_block_hash(rpc_client: RpcClient, block_number: BlockNumber) -> Result<H256, Error> {
-	let params = rpc_params![Some(block_number)];
-	let block_hash: H256 = rpc_client
-		.request("chain_getBlockHash", params)
-		.await
-		.map_err(|_| Error::PaymentValidationFailed)?;
+	#[cfg(not(debug_assertions))]
+	shared::init_tracker();
 
-	Ok(block_hash)
+	Ok(Json(receipt))
 }
@@ -13,14 +13,63 @@
 // You should have received a copy of the GNU General Public License
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::Error;
-use rocket::get;
-use shared::registry::registered_paras;
+use crate::{relay_chain, Error, LOG_TARGET};
+use rocket::{
+	get,
+	serde::{Deserialize, Serialize},
+};
+use shared::{
+	audit::para_history,
+	registry::{registered_paras, RegistryError},
+};
+use types::{ParaId, Parachain, RelayChain, Timestamp};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RegisteredPara {
+	pub name: String,
+	pub rpcs: Vec<String>,
+	pub para_id: ParaId,
+	pub relay_chain: RelayChain,
+	/// The timestamp when the subscription expires, `None` if it never expires.
+	pub expiry_timestamp: Option<Timestamp>,
+	/// Whether the subscription never expires, e.g. because payments were disabled when the
+	/// parachain got registered.
+	pub never_expires: bool,
+	pub plan: Option<String>,
+}
+
+impl From<Parachain> for RegisteredPara {
+	fn from(para: Parachain) -> Self {
+		let never_expires = para.expiry_timestamp == Timestamp::MAX;
+		Self {
+			name: para.name,
+			rpcs: para.rpcs,
+			para_id: para.para_id,
+			relay_chain: para.relay_chain,
+			expiry_timestamp: Some(para.expiry_timestamp).filter(|_| !never_expires),
+			never_expires,
+			plan: para.plan,
+		}
+	}
+}
 
 /// Query all the registered parachains.
 #[get("/registry")]
 pub fn registry() -> Result<String, Error> {
-	let registered_paras = registered_paras();
+	let registered_paras: Vec<RegisteredPara> =
+		registered_paras()?.into_iter().map(RegisteredPara::from).collect();
 
 	serde_json::to_string(&registered_paras).map_err(|_| Error::InvalidData)
 }
+
+/// Query the audit log of all the registry changes made to a parachain, oldest first.
+#[get("/registry/<relay>/<para_id>/history")]
+pub fn registry_history(relay: &str, para_id: ParaId) -> Result<String, Error> {
+	let history = para_history(relay_chain(relay)?, para_id).map_err(|e| {
+		log::error!(target: LOG_TARGET, "Failed to read the audit log: {:?}", e);
+		Error::RegistryError(RegistryError::AuditFailed)
+	})?;
+
+	serde_json::to_string(&history).map_err(|_| Error::InvalidData)
+}
@@ -0,0 +1,153 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use rocket::get;
+use shared::{
+	config::{config, AssetCost, AssetId, PaymentInfo},
+	current_timestamp,
+	registry::registered_para,
+	watcher::{payment_remark, remark_call},
+};
+use types::{ParaId, RelayChain, Timestamp};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SubscriptionStatus {
+	/// Whether the parachain is registered.
+	pub registered: bool,
+	/// The timestamp when the subscription expires.
+	///
+	/// `None` if the parachain isn't registered or its subscription never expires.
+	pub expiry_timestamp: Option<Timestamp>,
+	/// Whether the subscription never expires, e.g. because payments were disabled when the
+	/// parachain got registered.
+	pub never_expires: bool,
+	/// Whether the subscription can be renewed now.
+	///
+	/// Always the case for parachains which aren't registered, since they can be registered at
+	/// any time.
+	pub renewal_open: bool,
+	/// Seconds until the renewal window opens, zero if it is already open.
+	///
+	/// `None` if payments are disabled for the relay chain of the parachain or the subscription
+	/// never expires.
+	pub renewal_opens_in: Option<Timestamp>,
+	/// Describes the payment required to register the parachain or renew its subscription.
+	///
+	/// `None` if payments are disabled for the relay chain of the parachain.
+	pub payment: Option<PaymentQuote>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PaymentQuote {
+	/// The account the payment has to be sent to.
+	pub receiver: String,
+	/// The cost of a single subscription period in the native currency.
+	pub cost: String,
+	/// The duration covered by a single subscription period.
+	pub subscription_duration: Timestamp,
+	/// The cost of a single subscription period in each of the accepted assets.
+	pub assets: Vec<AssetQuote>,
+	/// The plans which can be bought instead of single subscription periods.
+	pub plans: Vec<PlanQuote>,
+	/// The remark which the payment extrinsic has to make.
+	pub remark: String,
+	/// The hex encoded `System::remark_with_event` call making the remark.
+	///
+	/// Has to be submitted along with the transfer, e.g. within a `Utility::batch_all` call.
+	pub remark_call: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AssetQuote {
+	pub asset_id: AssetId,
+	pub cost: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PlanQuote {
+	pub name: String,
+	pub cost: String,
+	pub asset_costs: Vec<AssetQuote>,
+	pub duration: Timestamp,
+}
+
+/// Query the subscription status of a parachain along with the payment required to renew it.
+#[get("/subscription/<relay>/<para_id>")]
+pub fn subscription(relay: &str, para_id: ParaId) -> Result<String, Error> {
+	let relay_chain = relay_chain(relay)?;
+	let para = registered_para(relay_chain.clone(), para_id)?;
+	let payment_info = config().payment_info_for(&relay_chain).cloned();
+
+	let never_expires = para.as_ref().is_some_and(|para| para.expiry_timestamp == Timestamp::MAX);
+
+	let now = current_timestamp();
+	let renewal_opens_in = match (&para, &payment_info) {
+		(Some(_), _) if never_expires => None,
+		(Some(para), Some(payment_info)) => Some(
+			para.expiry_timestamp
+				.saturating_sub(payment_info.renewal_period)
+				.saturating_sub(now),
+		),
+		(None, Some(_)) => Some(0),
+		(_, None) => None,
+	};
+
+	let status = SubscriptionStatus {
+		registered: para.is_some(),
+		expiry_timestamp: para.map(|para| para.expiry_timestamp).filter(|_| !never_expires),
+		never_expires,
+		renewal_open: renewal_opens_in == Some(0),
+		renewal_opens_in,
+		payment: payment_info.map(|payment_info| quote(&(relay_chain, para_id), payment_info)),
+	};
+
+	serde_json::to_string(&status).map_err(|_| Error::InvalidData)
+}
+
+fn quote(para: &(RelayChain, ParaId), payment_info: PaymentInfo) -> PaymentQuote {
+	let asset_quotes = |asset_costs: &[AssetCost]| {
+		asset_costs
+			.iter()
+			.map(|asset| AssetQuote { asset_id: asset.asset_id, cost: asset.cost.clone() })
+			.collect()
+	};
+
+	PaymentQuote {
+		receiver: payment_info.receiver.to_string(),
+		cost: payment_info.cost.clone(),
+		subscription_duration: payment_info.subscription_duration,
+		assets: asset_quotes(&payment_info.assets),
+		plans: payment_info
+			.plans
+			.iter()
+			.map(|plan| PlanQuote {
+				name: plan.name.clone(),
+				cost: plan.cost.clone(),
+				asset_costs: asset_quotes(&plan.asset_costs),
+				duration: plan.duration,
+			})
+			.collect(),
+		remark: String::from_utf8_lossy(&payment_remark(para)).into_owned(),
+		remark_call: format!(
+			"0x{}",
+			remark_call(para).iter().map(|byte| format!("{:02x}", byte)).collect::<String>()
+		),
+	}
+}
@@ -0,0 +1,178 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+use rocket::{
+	http::{ContentType, Header, Status},
+	local::blocking::{Client, LocalResponse},
+	routes,
+};
+use routes::{
+	admin::{patch_para, remove_para, set_expiry, ExpiryData, ParaUpdate},
+	Error, ErrorResponse,
+};
+use shared::{
+	audit::{para_history, RegistryAction},
+	chaindata::get_para,
+	registry::{registered_para, registered_paras},
+};
+use types::RelayChain::*;
+
+mod mock;
+use mock::MockEnvironment;
+
+const ADMIN_TOKEN: &str = "Bearer mock-admin-token";
+
+#[test]
+fn admin_routes_require_the_admin_token() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build()
+			.mount("/", routes![remove_para])
+			.register("/", routes::catchers());
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let response = client.delete("/admin/registry/polkadot/2000").dispatch();
+		assert_eq!(response.status(), Status::Unauthorized);
+		assert_eq!(response.content_type(), Some(ContentType::JSON));
+		assert_eq!(parse_err_response(response), Error::Unauthorized);
+
+		// Tokens of the same length or prefixes of the token are rejected as well.
+		for token in ["Bearer wrong-token", "Bearer mock-admin-tokem", "Bearer mock-admin"] {
+			let response = client
+				.delete("/admin/registry/polkadot/2000")
+				.header(Header::new("Authorization", token))
+				.dispatch();
+			assert_eq!(response.status(), Status::Unauthorized);
+			assert_eq!(parse_err_response(response), Error::Unauthorized);
+		}
+
+		assert!(registered_para(Polkadot, 2000).unwrap().is_some());
+	});
+}
+
+#[test]
+fn removing_para_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![remove_para]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let response = client
+			.delete("/admin/registry/polkadot/2000")
+			.header(Header::new("Authorization", ADMIN_TOKEN))
+			.dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		assert_eq!(registered_paras().unwrap(), vec![get_para(Polkadot, 2004).unwrap()]);
+
+		let history = para_history(Polkadot, 2000).unwrap();
+		assert_eq!(history.len(), 1);
+		assert_eq!(history[0].action, RegistryAction::Removal);
+		assert_eq!(history[0].actor, "admin");
+
+		// Removing an unregistered para fails.
+		let response = client
+			.delete("/admin/registry/polkadot/2000")
+			.header(Header::new("Authorization", ADMIN_TOKEN))
+			.dispatch();
+		assert_eq!(parse_err_response(response), Error::NotRegistered);
+
+		// So does removing a para of an unknown relay chain.
+		let response = client
+			.delete("/admin/registry/foo/2004")
+			.header(Header::new("Authorization", ADMIN_TOKEN))
+			.dispatch();
+		assert_eq!(response.status(), Status::NotFound);
+		assert_eq!(parse_err_response(response), Error::NotRegistered);
+	});
+}
+
+#[test]
+fn patching_para_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![patch_para]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let update = ParaUpdate { name: None, rpcs: Some(vec!["wss://rpc.example.com".into()]) };
+
+		let response = client
+			.patch("/admin/registry/polkadot/2000")
+			.header(ContentType::JSON)
+			.header(Header::new("Authorization", ADMIN_TOKEN))
+			.body(serde_json::to_string(&update).unwrap())
+			.dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let registered = registered_para(Polkadot, 2000).unwrap().unwrap();
+		assert_eq!(registered.rpcs, vec!["wss://rpc.example.com".to_string()]);
+		// The fields which weren't provided remain unchanged.
+		assert_eq!(registered.name, para.name);
+
+		let update = ParaUpdate { name: Some("Renamed".into()), rpcs: None };
+		let response = client
+			.patch("/admin/registry/polkadot/2000")
+			.header(ContentType::JSON)
+			.header(Header::new("Authorization", ADMIN_TOKEN))
+			.body(serde_json::to_string(&update).unwrap())
+			.dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let registered = registered_para(Polkadot, 2000).unwrap().unwrap();
+		assert_eq!(registered.name, "Renamed");
+		assert_eq!(registered.rpcs, vec!["wss://rpc.example.com".to_string()]);
+	});
+}
+
+#[test]
+fn setting_expiry_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![set_expiry]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let set_expiry = |expiry_timestamp| {
+			let response = client
+				.put("/admin/registry/polkadot/2000/expiry")
+				.header(ContentType::JSON)
+				.header(Header::new("Authorization", ADMIN_TOKEN))
+				.body(serde_json::to_string(&ExpiryData { expiry_timestamp }).unwrap())
+				.dispatch();
+			assert_eq!(response.status(), Status::Ok);
+		};
+
+		set_expiry(100);
+		let registered = registered_para(Polkadot, 2000).unwrap().unwrap();
+		assert_eq!(registered.expiry_timestamp, 100);
+
+		// Moving the expiry back isn't recorded as an extension.
+		set_expiry(42);
+		let registered = registered_para(Polkadot, 2000).unwrap().unwrap();
+		assert_eq!(registered.expiry_timestamp, 42);
+
+		let history = para_history(Polkadot, 2000).unwrap();
+		assert_eq!(history.len(), 2);
+		assert_eq!(history[0].action, RegistryAction::SubscriptionExtension);
+		assert_eq!(history[0].old_expiry, Some(para.expiry_timestamp));
+		assert_eq!(history[0].new_expiry, Some(100));
+		assert_eq!(history[1].action, RegistryAction::SubscriptionReduction);
+		assert_eq!(history[1].old_expiry, Some(100));
+		assert_eq!(history[1].new_expiry, Some(42));
+	});
+}
+
+fn parse_err_response<'a>(response: LocalResponse<'a>) -> Error {
+	let body = response.into_string().unwrap();
+	let response: ErrorResponse = serde_json::from_str(&body).expect("can't parse value");
+	response.try_into().expect("unknown error")
+}
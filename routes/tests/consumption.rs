@@ -13,18 +13,33 @@
 // You should have received a copy of the GNU General Public License
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
+use chrono::FixedOffset;
 use rocket::{
-	http::Status,
+	http::{Accept, ContentType, Header, Status},
 	local::blocking::{Client, LocalResponse},
 	routes,
 };
 use routes::{
-	consumption::{consumption, group_consumption, AggregatedData, Grouping},
-	Error,
+	consumption::{
+		consumption, csv_header, group_consumption, group_consumption_in, AggregatedData, Bucket,
+		ConsumptionPage, Grouping, Statistic, Timezone,
+	},
+	Error, ErrorResponse,
 };
-use shared::{chaindata::get_para, registry::update_registry, reset_mock_environment};
-use std::collections::HashMap;
-use types::{RelayChain::*, WeightConsumption};
+use shared::{
+	archive::{archived_months, rotate_consumption},
+	chaindata::get_para,
+	config::output_directory,
+	consumption::{
+		get_consumption, get_live_consumption, migrate_consumption, repair_consumption,
+		verify_consumption, write_batch_consumption, write_consumption, SCHEMA_VERSION,
+	},
+	registry::update_registry,
+	reset_mock_environment,
+	rollup::{compute_rollups, get_rollups, write_rollups},
+};
+use std::io::Write;
+use types::{RelayChain::*, RollupPeriod, WeightConsumption};
 
 mod mock;
 use mock::{mock_consumption, MockEnvironment};
@@ -55,10 +70,48 @@ fn parachain_not_found_handled() {
 		let client = Client::tracked(rocket).expect("valid rocket instance");
 
 		let response = client.get("/consumption/polkadot/42").dispatch();
-		assert_eq!(response.status(), Status::InternalServerError);
+		assert_eq!(response.status(), Status::NotFound);
 
 		let err = parse_err_response(response);
 		assert_eq!(err, Error::NotRegistered);
+
+		let response = client.get("/consumption/foo/2000").dispatch();
+		assert_eq!(response.status(), Status::NotFound);
+		assert_eq!(parse_err_response(response), Error::NotRegistered);
+	});
+}
+
+#[test]
+fn invalid_parameters_handled() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build()
+			.mount("/", routes![consumption])
+			.register("/", routes::catchers());
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		// Invalid values are rejected instead of being ignored.
+		for (query, param) in [
+			("grouping=fortnight", "grouping"),
+			("bucket=0d", "bucket"),
+			("stats=mean,p42", "stats"),
+			("format=xml", "format"),
+			("from_block=first", "from_block"),
+			("page=-1", "page"),
+			("precision=high", "precision"),
+		] {
+			let response = client.get(format!("/consumption/polkadot/2000?{}", query)).dispatch();
+			assert_eq!(response.status(), Status::BadRequest);
+			assert_eq!(parse_err_response(response), Error::InvalidParameter(param.into()));
+		}
+
+		// The path is rejected before reaching the route.
+		let response = client.get("/consumption/polkadot/first").dispatch();
+		assert_eq!(response.status(), Status::UnprocessableEntity);
+		assert_eq!(parse_err_response(response), Error::InvalidRequest);
+
+		let response = client.get("/consumption/polkadot").dispatch();
+		assert_eq!(response.status(), Status::NotFound);
+		assert_eq!(parse_err_response(response), Error::NotFound);
 	});
 }
 
@@ -74,7 +127,7 @@ fn consumption_data_not_found_handled() {
 	assert!(update_registry(vec![get_para(Polkadot, 2000).unwrap()]).is_ok());
 
 	let response = client.get("/consumption/polkadot/2000").dispatch();
-	assert_eq!(response.status(), Status::InternalServerError);
+	assert_eq!(response.status(), Status::NotFound);
 
 	let err = parse_err_response(response);
 	assert_eq!(err, Error::ConsumptionDataNotFound);
@@ -128,6 +181,81 @@ fn pagination_works() {
 
 		let consumption_data = parse_ok_response(response);
 		assert!(consumption_data.is_empty());
+
+		// CASE 5: An empty page size is rejected.
+		let response = client.get("/consumption/polkadot/2000?page_size=0").dispatch();
+		assert_eq!(response.status(), Status::BadRequest);
+		assert_eq!(parse_err_response(response), Error::InvalidPageSize);
+	});
+}
+
+#[test]
+fn pagination_applies_to_groups() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let grouped = group_consumption(
+			mock_consumption().get(&para).unwrap().clone(),
+			Grouping::Bucket(Bucket::Blocks(2)),
+		);
+		assert_eq!(grouped.len(), 3);
+
+		let response = client.get("/consumption/polkadot/2000?bucket=2b&page_size=2").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(
+			parse_page(response),
+			ConsumptionPage { items: grouped[..2].to_vec(), total: 3, next_cursor: Some(4) }
+		);
+
+		let response = client
+			.get("/consumption/polkadot/2000?bucket=2b&page_size=2&cursor=4")
+			.dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(
+			parse_page(response),
+			ConsumptionPage { items: grouped[2..].to_vec(), total: 3, next_cursor: None }
+		);
+	});
+}
+
+#[test]
+fn cursor_pagination_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let mock_data = mock_consumption().get(&para).unwrap().clone();
+
+		// Walk over all the pages by following the cursors.
+		let mut items = vec![];
+		let mut cursor = None;
+		loop {
+			let query = match cursor {
+				Some(cursor) => format!("/consumption/polkadot/2000?page_size=3&cursor={}", cursor),
+				None => "/consumption/polkadot/2000?page_size=3".to_string(),
+			};
+			let response = client.get(query).dispatch();
+			assert_eq!(response.status(), Status::Ok);
+
+			let page = parse_page(response);
+			assert_eq!(page.total, mock_data.len());
+			items.extend(page.items);
+
+			cursor = page.next_cursor;
+			if cursor.is_none() {
+				break;
+			}
+		}
+
+		assert_eq!(items, group_consumption(mock_data, Grouping::BlockNumber));
+
+		// A cursor past the last block results in an empty page.
+		let response = client.get("/consumption/polkadot/2000?cursor=100").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(parse_page(response), ConsumptionPage { total: 4, ..Default::default() });
 	});
 }
 
@@ -198,6 +326,112 @@ fn timestamp_based_filtering_works() {
 	});
 }
 
+#[test]
+fn iso_date_filtering_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2004).unwrap();
+		// 2024-03-01T00:00:00Z and the blocks following it.
+		let first = 1_709_251_200_000;
+		let day = RollupPeriod::Day.duration();
+		let consumption: Vec<WeightConsumption> = [first, first + day - 1, first + day]
+			.into_iter()
+			.enumerate()
+			.map(|(i, timestamp)| WeightConsumption {
+				block_number: 10 + i as u32,
+				timestamp,
+				ref_time: (0.5, 0.0, 0.0).into(),
+				proof_size: (0.5, 0.0, 0.0).into(),
+			})
+			.collect();
+		consumption
+			.iter()
+			.for_each(|c| write_consumption(para.clone(), c.clone(), None).unwrap());
+
+		let blocks = |query: &str| -> Vec<String> {
+			let response = client.get(format!("/consumption/polkadot/2004?{}", query)).dispatch();
+			assert_eq!(response.status(), Status::Ok);
+			parse_ok_response(response).into_iter().map(|data| data.group).collect()
+		};
+
+		// A date refers to the whole day when used as the end of the range.
+		assert_eq!(blocks("start=2024-03-01&end=2024-03-01"), vec!["10", "11"]);
+		assert_eq!(blocks("start=2024-03-02"), vec!["12"]);
+		assert_eq!(blocks("start=2024-03-01T23:59:59.999Z"), vec!["11", "12"]);
+		assert_eq!(blocks("start=2024-03-01T23:59:59&end=2024-03-02T00:00:00"), vec!["11", "12"]);
+		// Offsets from UTC are supported.
+		assert_eq!(blocks("end=2024-03-02T01:00:00%2B02:00"), vec!["1", "10"]);
+		// Timestamps in milliseconds can still be used.
+		assert_eq!(blocks(&format!("start={}", first + day)), vec!["12"]);
+
+		let response = client.get("/consumption/polkadot/2004?start=2024-13-01").dispatch();
+		assert_eq!(response.status(), Status::BadRequest);
+		assert_eq!(parse_err_response(response), Error::InvalidTime);
+	});
+}
+
+#[test]
+fn block_range_filtering_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let mock_data = mock_consumption().get(&para).unwrap().clone();
+
+		let response = client.get("/consumption/polkadot/2000?from_block=2&to_block=3").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let expected_data = group_consumption(
+			mock_data
+				.iter()
+				.filter(|c| (2..=3).contains(&c.block_number))
+				.cloned()
+				.collect(),
+			Grouping::BlockNumber,
+		);
+		assert_eq!(parse_ok_response(response), expected_data);
+
+		// Can be combined with the time based filtering and grouping.
+		let response = client
+			.get("/consumption/polkadot/2000?from_block=2&end=12&grouping=day")
+			.dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let expected_data = group_consumption(
+			mock_data
+				.into_iter()
+				.filter(|c| c.block_number >= 2 && c.timestamp <= 12)
+				.collect(),
+			Grouping::Day,
+		);
+		assert_eq!(parse_ok_response(response), expected_data);
+	});
+}
+
+#[test]
+fn inverted_ranges_are_rejected() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		for query in
+			["start=12&end=6", "start=2024-03-02&end=2024-03-01", "from_block=3&to_block=2"]
+		{
+			let response = client.get(format!("/consumption/polkadot/2000?{}", query)).dispatch();
+			assert_eq!(response.status(), Status::BadRequest);
+			assert_eq!(parse_err_response(response), Error::InvalidRange);
+		}
+
+		// A single block or timestamp is a valid range.
+		let response = client.get("/consumption/polkadot/2000?from_block=3&to_block=3").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(parse_ok_response(response).len(), 1);
+	});
+}
+
 #[test]
 fn pagination_and_timestamp_filtering_works() {
 	MockEnvironment::new().execute_with(|| {
@@ -297,17 +531,712 @@ fn grouping_works() {
 		let expected_consumption =
 			group_consumption(mock_consumption().get(&para).unwrap().clone(), Grouping::Year);
 		assert_eq!(consumption_data, expected_consumption);
+
+		// Grouping by block number explicitly, by week and by quarter:
+		for (query, grouping) in [
+			("block_number", Grouping::BlockNumber),
+			("week", Grouping::Week),
+			("quarter", Grouping::Quarter),
+		] {
+			let response =
+				client.get(format!("/consumption/polkadot/2000?grouping={}", query)).dispatch();
+			assert_eq!(response.status(), Status::Ok);
+
+			let expected_consumption =
+				group_consumption(mock_consumption().get(&para).unwrap().clone(), grouping);
+			assert_eq!(parse_ok_response(response), expected_consumption);
+		}
+
+		// Grouping into custom buckets:
+		let response = client.get("/consumption/polkadot/2000?bucket=2b").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let consumption_data = parse_ok_response(response);
+		let expected_consumption = group_consumption(
+			mock_consumption().get(&para).unwrap().clone(),
+			Grouping::Bucket(Bucket::Blocks(2)),
+		);
+		assert_eq!(consumption_data, expected_consumption);
+	});
+}
+
+#[test]
+fn custom_grouping_works() {
+	let hour = RollupPeriod::Hour.duration();
+	let day = RollupPeriod::Day.duration();
+	// 2024-03-31T22:00 UTC, followed by a block every 3 hours.
+	let first = 1_711_922_400_000;
+	let consumption: Vec<WeightConsumption> = (0..10)
+		.map(|i| WeightConsumption {
+			block_number: 8 + i,
+			timestamp: first + i as u64 * 3 * hour,
+			ref_time: (0.5, 0.0, 0.0).into(),
+			proof_size: (0.5, 0.0, 0.0).into(),
+		})
+		.collect();
+
+	let groups = |grouping, tz: Timezone| -> Vec<(String, usize)> {
+		group_consumption_in(consumption.clone(), grouping, tz)
+			.into_iter()
+			.map(|data| (data.group, data.count))
+			.collect()
+	};
+	let utc = Timezone::utc();
+	let cest = Timezone::Fixed(FixedOffset::east_opt(2 * 3600).unwrap());
+
+	assert_eq!(
+		groups(Grouping::Quarter, utc),
+		vec![("2024-Q1".to_string(), 1), ("2024-Q2".to_string(), 9)]
+	);
+	// In CEST all the blocks are in the second quarter.
+	assert_eq!(groups(Grouping::Quarter, cest), vec![("2024-Q2".to_string(), 10)]);
+	assert_eq!(
+		groups(Grouping::Week, utc),
+		vec![("2024-W13".to_string(), 1), ("2024-W14".to_string(), 9)]
+	);
+	assert_eq!(
+		groups(Grouping::Day, cest),
+		vec![("2024-04-01".to_string(), 8), ("2024-04-02".to_string(), 2)]
+	);
+
+	// The buckets are aligned to the local time.
+	assert_eq!(
+		groups(Grouping::Bucket(Bucket::Interval(day)), utc),
+		vec![
+			("2024-03-31T00:00".to_string(), 1),
+			("2024-04-01T00:00".to_string(), 8),
+			("2024-04-02T00:00".to_string(), 1)
+		]
+	);
+	assert_eq!(
+		groups(Grouping::Bucket(Bucket::Interval(12 * hour)), cest),
+		vec![
+			("2024-04-01T00:00".to_string(), 4),
+			("2024-04-01T12:00".to_string(), 4),
+			("2024-04-02T00:00".to_string(), 2)
+		]
+	);
+
+	// The block buckets are ordered by block number.
+	assert_eq!(
+		groups(Grouping::Bucket(Bucket::Blocks(5)), utc),
+		vec![("5-9".to_string(), 2), ("10-14".to_string(), 5), ("15-19".to_string(), 3)]
+	);
+}
+
+#[test]
+fn zone_groups_follow_daylight_saving_time() {
+	let hour = RollupPeriod::Hour.duration();
+	// 2024-10-26T20:00 UTC, followed by a block every hour. The daylight saving time ends in Berlin
+	// at 2024-10-27T01:00 UTC, when the clocks are turned back from 03:00 to 02:00.
+	let first = 1_729_972_800_000;
+	let consumption: Vec<WeightConsumption> = (0..28)
+		.map(|i| WeightConsumption {
+			block_number: i,
+			timestamp: first + i as u64 * hour,
+			ref_time: (0.5, 0.0, 0.0).into(),
+			proof_size: (0.5, 0.0, 0.0).into(),
+		})
+		.collect();
+
+	let groups = |grouping, tz: Timezone| -> Vec<(String, usize)> {
+		group_consumption_in(consumption.clone(), grouping, tz)
+			.into_iter()
+			.map(|data| (data.group, data.count))
+			.collect()
+	};
+	let berlin: Timezone = "Europe/Berlin".parse().unwrap();
+	let cest = Timezone::Fixed(FixedOffset::east_opt(2 * 3600).unwrap());
+
+	// The day on which the clocks are turned back lasts 25 hours.
+	assert_eq!(
+		groups(Grouping::Day, berlin),
+		vec![
+			("2024-10-26".to_string(), 2),
+			("2024-10-27".to_string(), 25),
+			("2024-10-28".to_string(), 1)
+		]
+	);
+	assert_eq!(
+		groups(Grouping::Day, cest),
+		vec![
+			("2024-10-26".to_string(), 2),
+			("2024-10-27".to_string(), 24),
+			("2024-10-28".to_string(), 2)
+		]
+	);
+
+	// The buckets are aligned to the local time of each block, so the repeated hour falls into
+	// the same bucket.
+	assert_eq!(
+		groups(Grouping::Bucket(Bucket::Interval(12 * hour)), berlin),
+		vec![
+			("2024-10-26T12:00".to_string(), 2),
+			("2024-10-27T00:00".to_string(), 13),
+			("2024-10-27T12:00".to_string(), 12),
+			("2024-10-28T00:00".to_string(), 1)
+		]
+	);
+}
+
+#[test]
+fn timezone_and_bucket_parameters_work() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let mock_data = mock_consumption().get(&para).unwrap().clone();
+
+		let response = client.get("/consumption/polkadot/2000?grouping=day&tz=-05:00").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let consumption_data = parse_ok_response(response);
+		let expected_consumption = group_consumption_in(
+			mock_data.clone(),
+			Grouping::Day,
+			Timezone::Fixed(FixedOffset::west_opt(5 * 3600).unwrap()),
+		);
+		assert_eq!(consumption_data, expected_consumption);
+		assert_eq!(consumption_data[0].group, "1969-12-31");
+
+		// The bucket takes precedence over the grouping.
+		let response = client
+			.get("/consumption/polkadot/2000?grouping=day&bucket=15m&tz=UTC")
+			.dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let consumption_data = parse_ok_response(response);
+		let expected_consumption = group_consumption(
+			mock_data.clone(),
+			Grouping::Bucket(Bucket::Interval(15 * 60 * 1000)),
+		);
+		assert_eq!(consumption_data, expected_consumption);
+		assert_eq!(consumption_data[0].group, "1970-01-01T00:00");
+
+		// A positive offset has to be URL-encoded.
+		let response = client.get("/consumption/polkadot/2000?bucket=1h&tz=%2B02:00").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let consumption_data = parse_ok_response(response);
+		let expected_consumption = group_consumption_in(
+			mock_data.clone(),
+			Grouping::Bucket(Bucket::Interval(3600 * 1000)),
+			Timezone::Fixed(FixedOffset::east_opt(2 * 3600).unwrap()),
+		);
+		assert_eq!(consumption_data, expected_consumption);
+		assert_eq!(consumption_data[0].group, "1970-01-01T02:00");
+
+		// Zones are given by their IANA names.
+		let response = client
+			.get("/consumption/polkadot/2000?grouping=hour&tz=Europe/Berlin")
+			.dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let consumption_data = parse_ok_response(response);
+		let expected_consumption =
+			group_consumption_in(mock_data, Grouping::Hour, "Europe/Berlin".parse().unwrap());
+		assert_eq!(consumption_data, expected_consumption);
+		assert_eq!(consumption_data[0].group, "1970-01-01T01:00");
+
+		// A raw `+` is decoded as a space, and unknown zones are rejected.
+		for tz in ["+02:00", "Europe/Atlantis", "CEST"] {
+			let response = client
+				.get(format!("/consumption/polkadot/2000?grouping=day&tz={}", tz))
+				.dispatch();
+			assert_eq!(response.status(), Status::BadRequest);
+			assert_eq!(parse_err_response(response), Error::InvalidTimezone);
+		}
+	});
+}
+
+#[test]
+fn precision_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2004).unwrap();
+		let consumption = WeightConsumption {
+			block_number: 2,
+			timestamp: 6,
+			ref_time: (0.123456789, 0.000042, 0.5).into(),
+			proof_size: (0.98765, 0.0004, 0.25).into(),
+		};
+		write_consumption(para.clone(), consumption.clone(), None).unwrap();
+
+		// The values are stored with full precision.
+		let response = client.get("/consumption/polkadot/2004?start=6").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let consumption_data = parse_ok_response(response);
+		assert_eq!(consumption_data, group_consumption(vec![consumption], Grouping::BlockNumber));
+
+		let response = client.get("/consumption/polkadot/2004?start=6&precision=3").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let consumption_data = parse_ok_response(response);
+		assert_eq!(
+			consumption_data,
+			vec![AggregatedData {
+				group: "2".to_string(),
+				ref_time: (0.123, 0.0, 0.5).into(),
+				proof_size: (0.988, 0.0, 0.25).into(),
+				count: 1,
+				first_block: 2,
+				last_block: 2,
+				// With a single block all the statistics are equal to its consumption.
+				ref_time_stats: Statistic::ALL.map(|stat| (stat, (0.123, 0.0, 0.5).into())).into(),
+				proof_size_stats: Statistic::ALL
+					.map(|stat| (stat, (0.988, 0.0, 0.25).into()))
+					.into(),
+			}]
+		);
+	});
+}
+
+#[test]
+fn statistics_work() {
+	MockEnvironment::new().execute_with(|| {
+		let para = get_para(Polkadot, 2004).unwrap();
+		let consumption: Vec<WeightConsumption> = (1..=10)
+			.map(|i| WeightConsumption {
+				block_number: i,
+				timestamp: 0,
+				ref_time: (i as f64 / 10.0, 0.0, 0.5).into(),
+				proof_size: (1.0 - i as f64 / 10.0, 0.25, 0.0).into(),
+			})
+			.collect();
+
+		let grouped = group_consumption(consumption.clone(), Grouping::Day);
+		assert_eq!(grouped.len(), 1);
+
+		let stat = |stat| grouped[0].ref_time_stats.get(&stat).unwrap().clone();
+		assert!((stat(Statistic::Mean).normal - 0.55).abs() < 1e-9);
+		assert_eq!(stat(Statistic::Min), (0.1, 0.0, 0.5).into());
+		assert_eq!(stat(Statistic::Max), (1.0, 0.0, 0.5).into());
+		assert_eq!(stat(Statistic::P50), (0.5, 0.0, 0.5).into());
+		assert_eq!(stat(Statistic::P90), (0.9, 0.0, 0.5).into());
+		assert_eq!(stat(Statistic::P99), (1.0, 0.0, 0.5).into());
+
+		// The values of each dispatch class are sorted separately.
+		let stat = |stat| grouped[0].proof_size_stats.get(&stat).unwrap().clone();
+		assert_eq!(stat(Statistic::Min).normal, 0.0);
+		assert_eq!(stat(Statistic::P90).normal, 0.8);
+		assert_eq!(stat(Statistic::P99), (0.9, 0.25, 0.0).into());
+
+		// The daily rollups contain the same statistics.
+		let rollups = compute_rollups(&consumption, RollupPeriod::Day);
+		write_rollups(para.clone(), RollupPeriod::Day, rollups.clone()).unwrap();
+		assert_eq!(get_rollups(para, RollupPeriod::Day).unwrap(), rollups);
+
+		let rolled_up = rollups[0].ref_time_percentiles.clone();
+		assert_eq!(rolled_up.normal.p50, 0.5);
+		assert_eq!(rolled_up.normal.p90, 0.9);
+		assert_eq!(rolled_up.normal.p99, 1.0);
+	});
+}
+
+#[test]
+fn stats_can_be_selected() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let expected_consumption: Vec<AggregatedData> =
+			group_consumption(mock_consumption().get(&para).unwrap().clone(), Grouping::Hour)
+				.into_iter()
+				.map(|mut data| {
+					data.ref_time_stats
+						.retain(|stat, _| [Statistic::Max, Statistic::P99].contains(stat));
+					data.proof_size_stats
+						.retain(|stat, _| [Statistic::Max, Statistic::P99].contains(stat));
+					data
+				})
+				.collect();
+
+		let response =
+			client.get("/consumption/polkadot/2000?grouping=hour&stats=max,p99").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(parse_ok_response(response), expected_consumption);
+
+		// No statistics at all:
+		let response = client.get("/consumption/polkadot/2000?grouping=hour&stats=").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert!(parse_ok_response(response)
+			.iter()
+			.all(|data| data.ref_time_stats.is_empty() && data.proof_size_stats.is_empty()));
+	});
+}
+
+#[test]
+fn csv_export_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let grouped = group_consumption(
+			mock_consumption().get(&para).unwrap().clone(),
+			Grouping::BlockNumber,
+		);
+		let stats = [Statistic::Max, Statistic::P99];
+
+		let expected: Vec<String> = [csv_header(&stats)]
+			.into_iter()
+			.chain(grouped.iter().map(|data| data.to_csv(&stats)))
+			.map(|row| row.join(","))
+			.collect();
+
+		let response = client.get("/consumption/polkadot/2000?format=csv&stats=max,p99").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(response.content_type(), Some(ContentType::CSV));
+		let body = response.into_string().unwrap();
+		assert_eq!(body.lines().collect::<Vec<_>>(), expected);
+		assert!(body.starts_with(
+			"group,first_block,last_block,count,ref_time_normal,ref_time_operational,"
+		));
+
+		// The pagination still applies, and the format can be negotiated.
+		let response = client
+			.get("/consumption/polkadot/2000?page_size=2&stats=max,p99")
+			.header(Accept::CSV)
+			.dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(response.content_type(), Some(ContentType::CSV));
+		assert_eq!(response.into_string().unwrap().lines().collect::<Vec<_>>(), expected[..3]);
+	});
+}
+
+#[test]
+fn ndjson_export_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let grouped = group_consumption(
+			mock_consumption().get(&para).unwrap().clone(),
+			Grouping::BlockNumber,
+		);
+		let parse = |body: String| -> Vec<AggregatedData> {
+			body.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+		};
+
+		let response = client.get("/consumption/polkadot/2000?format=ndjson").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(response.content_type(), Some(ContentType::new("application", "x-ndjson")));
+		assert_eq!(parse(response.into_string().unwrap()), grouped);
+
+		let response = client
+			.get("/consumption/polkadot/2000")
+			.header(Header::new("Accept", "application/x-ndjson"))
+			.dispatch();
+		assert_eq!(response.content_type(), Some(ContentType::new("application", "x-ndjson")));
+		assert_eq!(parse(response.into_string().unwrap()), grouped);
+
+		// The `format` takes precedence over the `Accept` header.
+		let response = client
+			.get("/consumption/polkadot/2000?format=json")
+			.header(Accept::CSV)
+			.dispatch();
+		assert_eq!(response.content_type(), Some(ContentType::JSON));
+		assert_eq!(parse_page(response).items, grouped);
+	});
+}
+
+#[test]
+fn daily_grouping_uses_rollups() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let mock_data = mock_consumption().get(&para).unwrap().clone();
+
+		let day = RollupPeriod::Day.duration();
+		let second_day: Vec<_> = (0..3)
+			.map(|i| WeightConsumption {
+				block_number: 14_401 + i,
+				timestamp: day + 6 * i as u64,
+				ref_time: (0.5, 0.25, 0.125).into(),
+				proof_size: (0.25, 0.5, 0.125).into(),
+			})
+			.collect();
+		second_day
+			.iter()
+			.for_each(|c| write_consumption(para.clone(), c.clone(), None).unwrap());
+
+		// The rollups were computed before the last block of the second day got produced. The
+		// rollup of the first day only covers some of its blocks, which shows that the per-block
+		// data of a rolled up day isn't aggregated again.
+		let rolled_up = [&mock_data[..2], &second_day[..2]].concat();
+		write_rollups(
+			para.clone(),
+			RollupPeriod::Day,
+			compute_rollups(&rolled_up, RollupPeriod::Day),
+		)
+		.unwrap();
+
+		let response = client.get("/consumption/polkadot/2000?grouping=day").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		// The last rolled up day is aggregated from the per-block data.
+		let consumption_data = parse_ok_response(response);
+		let mut expected_consumption = group_consumption(mock_data[..2].to_vec(), Grouping::Day);
+		expected_consumption.extend(group_consumption(second_day.clone(), Grouping::Day));
+		assert_eq!(consumption_data, expected_consumption);
+
+		// The days cut by the bounds of the range are aggregated from the per-block data within
+		// the bounds.
+		let response = client.get("/consumption/polkadot/2000?grouping=day&start=1").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		let mut expected_consumption = group_consumption(mock_data[1..].to_vec(), Grouping::Day);
+		expected_consumption.extend(group_consumption(second_day, Grouping::Day));
+		assert_eq!(parse_ok_response(response), expected_consumption);
+
+		// The totals match the ones of daily buckets, which are always aggregated from the
+		// per-block data.
+		let totals = |query: &str| -> Vec<_> {
+			let response = client.get(format!("/consumption/polkadot/2000?{}", query)).dispatch();
+			parse_ok_response(response)
+				.into_iter()
+				.map(|data| (data.count, data.first_block, data.last_block, data.ref_time))
+				.collect()
+		};
+		for range in ["end=12", "start=12&end=86400006", "start=6&end=12"] {
+			let daily = totals(&format!("grouping=day&{}", range));
+			assert!(!daily.is_empty());
+			assert_eq!(daily, totals(&format!("bucket=1d&{}", range)));
+		}
+	});
+}
+
+#[test]
+fn archived_consumption_is_read_transparently() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let mock_data = mock_consumption().get(&para).unwrap().clone();
+
+		// A block produced in February 1970, while all the mock data is from January.
+		let february = 40 * RollupPeriod::Day.duration();
+		let recent = WeightConsumption {
+			block_number: 576_001,
+			timestamp: february,
+			ref_time: (0.5, 0.25, 0.125).into(),
+			proof_size: (0.25, 0.5, 0.125).into(),
+		};
+		write_consumption(para.clone(), recent.clone(), None).unwrap();
+
+		rotate_consumption(para.clone(), february).unwrap();
+		// Rotating again doesn't change anything.
+		rotate_consumption(para.clone(), february).unwrap();
+
+		assert_eq!(archived_months(para.clone()), vec!["1970-01".to_string()]);
+		assert!(std::path::Path::new(&format!(
+			"{}/Polkadot-2000/1970-01.csv.gz",
+			output_directory(None)
+		))
+		.exists());
+		assert_eq!(get_live_consumption(para.clone(), None).unwrap().entries, vec![recent.clone()]);
+
+		let mut all_data = mock_data.clone();
+		all_data.push(recent.clone());
+		assert_eq!(get_consumption(para.clone(), None).unwrap().entries, all_data);
+
+		let response = client.get("/consumption/polkadot/2000").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(
+			parse_ok_response(response),
+			group_consumption(all_data.clone(), Grouping::BlockNumber)
+		);
+
+		let response = client.get("/consumption/polkadot/2000?start=6&end=12").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(
+			parse_ok_response(response),
+			group_consumption(
+				all_data.into_iter().filter(|c| c.timestamp >= 6 && c.timestamp <= 12).collect(),
+				Grouping::BlockNumber
+			)
+		);
+
+		// The archives are still read once all the data got archived.
+		rotate_consumption(para.clone(), 80 * RollupPeriod::Day.duration()).unwrap();
+		assert_eq!(
+			archived_months(para.clone()),
+			vec!["1970-01".to_string(), "1970-02".to_string()]
+		);
+		assert!(get_live_consumption(para.clone(), None).unwrap().entries.is_empty());
+
+		let response = client.get("/consumption/polkadot/2000?start=1000").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(
+			parse_ok_response(response),
+			group_consumption(vec![recent], Grouping::BlockNumber)
+		);
+	});
+}
+
+#[test]
+fn interrupted_rotation_does_not_duplicate_consumption() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![consumption]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let mut all_data = mock_consumption().get(&para).unwrap().clone();
+
+		let february = 40 * RollupPeriod::Day.duration();
+		let recent = WeightConsumption {
+			block_number: 576_001,
+			timestamp: february,
+			ref_time: (0.5, 0.25, 0.125).into(),
+			proof_size: (0.25, 0.5, 0.125).into(),
+		};
+		write_consumption(para.clone(), recent.clone(), None).unwrap();
+		all_data.push(recent);
+
+		// The archive got written, but the live file wasn't updated yet.
+		rotate_consumption(para.clone(), february).unwrap();
+		let path = format!("{}/{}-{}.csv", output_directory(None), para.relay_chain, para.para_id);
+		std::fs::remove_file(&path).unwrap();
+		write_batch_consumption(para.clone(), all_data.clone()).unwrap();
+
+		assert_eq!(get_consumption(para.clone(), None).unwrap().entries, all_data);
+
+		let response = client.get("/consumption/polkadot/2000?format=csv").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+		let body = response.into_string().unwrap();
+		let groups: Vec<&str> =
+			body.lines().skip(1).filter_map(|line| line.split(',').next()).collect();
+		assert_eq!(groups, vec!["1", "2", "3", "4", "576001"]);
+	});
+}
+
+#[test]
+fn corrupted_consumption_data_is_detected_and_repaired() {
+	MockEnvironment::new().execute_with(|| {
+		let para = get_para(Polkadot, 2000).unwrap();
+		let mock_data = mock_consumption().get(&para).unwrap().clone();
+
+		let path = format!("{}/{}-{}.csv", output_directory(None), para.relay_chain, para.para_id);
+		let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+		// A malformed row, a duplicate block and an interrupted write:
+		file.write_all(b"not,a,valid,row\n").unwrap();
+		file.write_all(b"2,6,0.1,0.4,0.2,0.2,0.3,0.3\n").unwrap();
+		file.write_all(b"5,24,0.1,0.").unwrap();
+
+		// The malformed rows are skipped but reported.
+		let consumption = get_consumption(para.clone(), None).unwrap();
+		assert_eq!(consumption.skipped, 2);
+
+		let report = verify_consumption(para.clone(), None).unwrap();
+		assert!(!report.is_ok());
+		// The first two lines contain the header.
+		assert_eq!(report.malformed, vec![7, 9]);
+		assert_eq!(report.duplicates, vec![8]);
+		assert!(report.torn);
+
+		assert_eq!(repair_consumption(para.clone(), None).unwrap(), report);
+		assert!(verify_consumption(para.clone(), None).unwrap().is_ok());
+
+		let consumption = get_consumption(para.clone(), None).unwrap();
+		assert_eq!(consumption.skipped, 0);
+		assert_eq!(consumption.entries, mock_data);
+	});
+}
+
+#[test]
+fn interrupted_write_is_discarded_on_next_write() {
+	MockEnvironment::new().execute_with(|| {
+		let para = get_para(Polkadot, 2004).unwrap();
+		let mut mock_data = mock_consumption().get(&para).unwrap().clone();
+
+		let path = format!("{}/{}-{}.csv", output_directory(None), para.relay_chain, para.para_id);
+		let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+		file.write_all(b"2,6,0.1").unwrap();
+
+		let consumption = WeightConsumption {
+			block_number: 2,
+			timestamp: 6,
+			ref_time: (0.1, 0.2, 0.3).into(),
+			proof_size: (0.3, 0.2, 0.1).into(),
+		};
+		write_consumption(para.clone(), consumption.clone(), None).unwrap();
+		mock_data.push(consumption);
+
+		assert!(verify_consumption(para.clone(), None).unwrap().is_ok());
+		assert_eq!(get_consumption(para, None).unwrap().entries, mock_data);
 	});
 }
 
+#[test]
+fn legacy_consumption_files_are_supported_and_migrated() {
+	MockEnvironment::new().execute_with(|| {
+		let para = get_para(Polkadot, 2000).unwrap();
+		let mock_data = mock_consumption().get(&para).unwrap().clone();
+
+		// Files written before the schema got versioned don't have a header.
+		let path = format!("{}/{}-{}.csv", output_directory(None), para.relay_chain, para.para_id);
+		let legacy: String =
+			mock_data.iter().map(|entry| entry.to_csv().join(",") + "\n").collect();
+		std::fs::write(&path, legacy).unwrap();
+
+		assert_eq!(get_consumption(para.clone(), None).unwrap().entries, mock_data);
+
+		assert_eq!(migrate_consumption(para.clone(), None).unwrap(), 1);
+		let content = std::fs::read_to_string(&path).unwrap();
+		assert!(content
+			.starts_with(&format!("#schema_version={}\nblock_number,timestamp,", SCHEMA_VERSION)));
+		assert_eq!(get_consumption(para.clone(), None).unwrap().entries, mock_data);
+
+		// Migrating an up to date file doesn't do anything.
+		assert_eq!(migrate_consumption(para.clone(), None).unwrap(), SCHEMA_VERSION);
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+	});
+}
+
+#[test]
+fn writing_to_legacy_consumption_file_migrates_it() {
+	MockEnvironment::new().execute_with(|| {
+		let para = get_para(Polkadot, 2004).unwrap();
+		let mut mock_data = mock_consumption().get(&para).unwrap().clone();
+
+		let path = format!("{}/{}-{}.csv", output_directory(None), para.relay_chain, para.para_id);
+		let legacy: String =
+			mock_data.iter().map(|entry| entry.to_csv().join(",") + "\n").collect();
+		std::fs::write(&path, legacy).unwrap();
+
+		let consumption = WeightConsumption {
+			block_number: 2,
+			timestamp: 6,
+			ref_time: (0.1, 0.2, 0.3).into(),
+			proof_size: (0.3, 0.2, 0.1).into(),
+		};
+		write_consumption(para.clone(), consumption.clone(), None).unwrap();
+		mock_data.push(consumption);
+
+		assert_eq!(migrate_consumption(para.clone(), None).unwrap(), SCHEMA_VERSION);
+		assert_eq!(get_consumption(para, None).unwrap().entries, mock_data);
+	});
+}
 
 fn parse_ok_response<'a>(response: LocalResponse<'a>) -> Vec<AggregatedData> {
+	parse_page(response).items
+}
 
+fn parse_page<'a>(response: LocalResponse<'a>) -> ConsumptionPage {
 	let body = response.into_string().unwrap();
 	serde_json::from_str(&body).expect("can't parse value")
 }
 
 fn parse_err_response<'a>(response: LocalResponse<'a>) -> Error {
 	let body = response.into_string().unwrap();
-	body.into()
+	let response: ErrorResponse = serde_json::from_str(&body).expect("can't parse value");
+	response.try_into().expect("unknown error")
 }
@@ -13,7 +13,6 @@
 // You should have received a copy of the GNU General Public License
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
-use polkadot_core_primitives::BlockNumber;
 use rocket::{
 	http::{ContentType, Status},
 	local::blocking::{Client, LocalResponse},
@@ -21,10 +20,13 @@ use rocket::{
 };
 use routes::{
 	extend_subscription::{extend_subscription, ExtendSubscriptionData},
-	Error,
+	Error, ErrorResponse,
 };
 use shared::{
 	chaindata::get_para,
+	config::config,
+	current_timestamp,
+	ledger::{PaymentReceipt, PaymentReference},
 	payment::PaymentError,
 	registry::{registered_para, update_registry},
 };
@@ -33,7 +35,8 @@ use types::RelayChain::*;
 mod mock;
 use mock::MockEnvironment;
 
-const PARA_2000_PAYMENT: BlockNumber = 9145403;
+const PARA_2000_PAYMENT: PaymentReference =
+	PaymentReference::Index { block_number: 9145403, index: 2 };
 
 #[test]
 fn extend_subscription_works() {
@@ -41,10 +44,11 @@ fn extend_subscription_works() {
 		let rocket = rocket::build().mount("/", routes![extend_subscription]);
 		let client = Client::tracked(rocket).expect("valid rocket instance");
 
+		let now = current_timestamp();
 		let para = get_para(Polkadot, 2000).unwrap();
 		let extend_subscription = ExtendSubscriptionData {
 			para: (para.relay_chain.clone(), para.para_id),
-			payment_block_number: PARA_2000_PAYMENT,
+			payment: PARA_2000_PAYMENT,
 		};
 
 		let response = client
@@ -55,9 +59,16 @@ fn extend_subscription_works() {
 
 		assert_eq!(response.status(), Status::Ok);
 
-		let registered = registered_para(Polkadot, 2000).unwrap();
+		let receipt: Option<PaymentReceipt> =
+			serde_json::from_str(&response.into_string().unwrap()).unwrap();
+		assert_eq!(receipt.unwrap().block_number, PARA_2000_PAYMENT.block_number());
+
+		let registered = registered_para(Polkadot, 2000).unwrap().unwrap();
 		// Ensure the `expiry_timestamp` got updated:
 		assert!(registered.expiry_timestamp != para.expiry_timestamp);
+		// The subscription of the mock para is expired, so it is extended from now on.
+		let payment_info = config().payment_info_for(&Polkadot).cloned().unwrap();
+		assert!(registered.expiry_timestamp >= now + payment_info.subscription_duration);
 	});
 }
 
@@ -67,10 +78,8 @@ fn cannot_extend_subscription_for_unregistered() {
 		let rocket = rocket::build().mount("/", routes![extend_subscription]);
 		let client = Client::tracked(rocket).expect("valid rocket instance");
 
-		let extend_subscription = ExtendSubscriptionData {
-			para: (Polkadot, 2006),
-			payment_block_number: PARA_2000_PAYMENT,
-		};
+		let extend_subscription =
+			ExtendSubscriptionData { para: (Polkadot, 2006), payment: PARA_2000_PAYMENT };
 
 		let response = client
 			.post("/extend-subscription")
@@ -78,6 +87,7 @@ fn cannot_extend_subscription_for_unregistered() {
 			.body(serde_json::to_string(&extend_subscription).unwrap())
 			.dispatch();
 
+		assert_eq!(response.status(), Status::NotFound);
 		assert_eq!(parse_err_response(response), Error::NotRegistered);
 	});
 }
@@ -88,10 +98,8 @@ fn cannot_extend_subscription_before_renewal_period() {
 		let rocket = rocket::build().mount("/", routes![extend_subscription]);
 		let client = Client::tracked(rocket).expect("valid rocket instance");
 
-		let extend_subscription = ExtendSubscriptionData {
-			para: (Polkadot, 2000),
-			payment_block_number: PARA_2000_PAYMENT,
-		};
+		let extend_subscription =
+			ExtendSubscriptionData { para: (Polkadot, 2000), payment: PARA_2000_PAYMENT };
 
 		let mut para = get_para(Polkadot, 2000).unwrap();
 		para.expiry_timestamp = u64::MAX;
@@ -107,6 +115,36 @@ fn cannot_extend_subscription_before_renewal_period() {
 	});
 }
 
+#[test]
+fn cannot_reuse_payment() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![extend_subscription]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let para = get_para(Polkadot, 2000).unwrap();
+		let extend_subscription = ExtendSubscriptionData {
+			para: (para.relay_chain.clone(), para.para_id),
+			payment: PARA_2000_PAYMENT,
+		};
+
+		let extend = client
+			.post("/extend-subscription")
+			.header(ContentType::JSON)
+			.body(serde_json::to_string(&extend_subscription).unwrap());
+
+		assert_eq!(extend.clone().dispatch().status(), Status::Ok);
+
+		// Reopen the renewal window.
+		update_registry(vec![para.clone()]).unwrap();
+
+		assert_eq!(
+			parse_err_response(extend.dispatch()),
+			Error::PaymentValidationError(PaymentError::AlreadyUsed)
+		);
+		assert_eq!(registered_para(Polkadot, 2000).unwrap().unwrap(), para);
+	});
+}
+
 #[test]
 fn providing_non_finalized_payment_block_number_fails() {
 	MockEnvironment::new().execute_with(|| {
@@ -116,7 +154,7 @@ fn providing_non_finalized_payment_block_number_fails() {
 		let para = get_para(Polkadot, 2000).unwrap();
 		let extend_subscription = ExtendSubscriptionData {
 			para: (para.relay_chain.clone(), para.para_id),
-			payment_block_number: 99999999,
+			payment: PaymentReference::Index { block_number: 99999999, index: 0 },
 		};
 
 		let response = client
@@ -142,7 +180,7 @@ fn payment_not_found_works() {
 		// We are extending the subscription for para 2004, but the payment is for para 2000.
 		let extend_subscription = ExtendSubscriptionData {
 			para: (para.relay_chain.clone(), para.para_id),
-			payment_block_number: PARA_2000_PAYMENT,
+			payment: PARA_2000_PAYMENT,
 		};
 
 		let response = client
@@ -160,5 +198,6 @@ fn payment_not_found_works() {
 
 fn parse_err_response<'a>(response: LocalResponse<'a>) -> Error {
 	let body = response.into_string().unwrap();
-	body.into()
+	let response: ErrorResponse = serde_json::from_str(&body).expect("can't parse value");
+	response.try_into().expect("unknown error")
 }
@@ -13,6 +13,9 @@
 // You should have received a copy of the GNU General Public License
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
+// Each test crate only uses a subset of the mocks.
+#![allow(dead_code)]
+
 #[cfg(test)]
 use maplit::hashmap;
 use scopeguard::guard;
@@ -21,7 +24,7 @@ use shared::{
 	reset_mock_environment,
 };
 use std::collections::HashMap;
-use types::{Parachain, RelayChain::*, WeightConsumption};
+use types::{ParaId, Parachain, RelayChain, RelayChain::*, WeightConsumption};
 
 #[derive(Default)]
 pub struct MockEnvironment {
@@ -58,6 +61,27 @@ impl MockEnvironment {
 	}
 }
 
+/// The config used by `with_config`.
+const MOCK_CONFIG: &str = "mock-config.toml";
+
+/// Executes the closure with the mock config modified by `edit`.
+pub fn with_config<R>(edit: impl FnOnce(String) -> String, execute: impl FnOnce() -> R) -> R {
+	let config = std::fs::read_to_string("config.toml").expect("Failed to read the mock config");
+	std::fs::write(MOCK_CONFIG, edit(config)).expect("Failed to write the mock config");
+	std::env::set_var("CONFIG_FILE", MOCK_CONFIG);
+
+	let _guard = guard((), |_| {
+		std::env::remove_var("CONFIG_FILE");
+		let _ = std::fs::remove_file(MOCK_CONFIG);
+	});
+
+	execute()
+}
+
+pub fn mock_para(relay: RelayChain, para_id: ParaId) -> Parachain {
+	get_para(relay, para_id).expect("The mock para is part of the chaindata")
+}
+
 pub fn mock_consumption() -> HashMap<Parachain, Vec<WeightConsumption>> {
 	hashmap! {
 		get_para(Polkadot, 2000).unwrap() => vec![
@@ -96,4 +120,3 @@ pub fn mock_consumption() -> HashMap<Parachain, Vec<WeightConsumption>> {
 		],
 	}
 }
-
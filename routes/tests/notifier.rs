@@ -0,0 +1,255 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+use shared::{
+	config::{config, NotifierConfig, WebhookFormat},
+	current_timestamp,
+	notifier::{
+		due_notifications, notify, notify_at, sent_notifications, Notification, NotificationKind::*,
+	},
+	registry::update_registry,
+};
+use std::{
+	io::{BufRead, BufReader, Write},
+	net::TcpListener,
+	sync::mpsc::{channel, Receiver},
+	thread,
+	time::{Duration, Instant},
+};
+use types::{Parachain, RelayChain::*, Timestamp};
+
+mod mock;
+use mock::{mock_para, MockEnvironment};
+
+const GRACE_PERIOD: Timestamp = 100;
+
+#[test]
+fn due_notifications_works() {
+	MockEnvironment::new().execute_with(|| {
+		let renewal_period = config().payment_info_for(&Polkadot).unwrap().renewal_period;
+		let now = 1_000_000;
+
+		let paras = vec![
+			para(Polkadot, 2000, now + renewal_period + 1),
+			para(Polkadot, 2004, now + renewal_period),
+			para(Polkadot, 2006, now),
+			para(Polkadot, 2012, now - GRACE_PERIOD),
+			para(Polkadot, 2030, Timestamp::MAX),
+			// No renewal window without payments.
+			para(Kusama, 2124, now + 1),
+			para(Kusama, 1001, now - 1),
+		];
+
+		let due: Vec<_> = due_notifications(&paras, now, GRACE_PERIOD)
+			.into_iter()
+			.map(|notification| (notification.para, notification.kind))
+			.collect();
+
+		assert_eq!(
+			due,
+			vec![
+				((Polkadot, 2004), RenewalWindowOpened),
+				((Polkadot, 2006), Expired),
+				((Polkadot, 2012), GracePeriodEnded),
+				((Kusama, 1001), Expired),
+			]
+		);
+	});
+}
+
+#[test]
+fn notifications_are_sent_once() {
+	MockEnvironment::new().execute_with(|| {
+		let webhook = MockWebhook::new(200);
+		let notifier = notifier(&webhook, WebhookFormat::Json);
+
+		let now = current_timestamp();
+		let expiry = now - 1;
+		update_registry(vec![para(Polkadot, 2000, expiry)]).unwrap();
+
+		let notified = notify_at(&notifier, now).unwrap();
+		assert_eq!(notified.len(), 1);
+		assert_eq!(notified[0].kind, Expired);
+
+		let received: Notification = serde_json::from_str(&webhook.next_request()).unwrap();
+		assert_eq!(received, notified[0]);
+
+		// Already sent:
+		assert!(notify_at(&notifier, now).unwrap().is_empty());
+		assert_eq!(sent_notifications(&notifier).unwrap().len(), 1);
+
+		// Renaming the parachain doesn't notify about the same expiry again.
+		let renamed = Parachain { name: "Renamed".into(), ..para(Polkadot, 2000, expiry) };
+		update_registry(vec![renamed]).unwrap();
+		assert!(notify_at(&notifier, now).unwrap().is_empty());
+		assert!(webhook.requests.try_iter().next().is_none());
+
+		// A renewed subscription is notified about again once it expires.
+		let renewed_expiry = now + 10;
+		update_registry(vec![para(Polkadot, 2000, renewed_expiry)]).unwrap();
+		let notified = notify_at(&notifier, now).unwrap();
+		assert_eq!(notified.len(), 1);
+		assert_eq!(notified[0].kind, RenewalWindowOpened);
+
+		let notified = notify_at(&notifier, renewed_expiry).unwrap();
+		assert_eq!(notified.len(), 1);
+		assert_eq!((notified[0].kind, notified[0].expiry_timestamp), (Expired, renewed_expiry));
+		assert_eq!(webhook.requests.try_iter().count(), 2);
+	});
+}
+
+#[test]
+fn failed_notifications_are_retried() {
+	MockEnvironment::new().execute_with(|| {
+		update_registry(vec![para(Polkadot, 2000, current_timestamp() - 1)]).unwrap();
+
+		let failing = MockWebhook::new(500);
+		assert!(notify(&notifier(&failing, WebhookFormat::Json)).unwrap().is_empty());
+		failing.next_request();
+
+		let webhook = MockWebhook::new(200);
+		let notifier = notifier(&webhook, WebhookFormat::Json);
+		assert!(sent_notifications(&notifier).unwrap().is_empty());
+
+		assert_eq!(notify(&notifier).unwrap().len(), 1);
+		assert_eq!(sent_notifications(&notifier).unwrap().len(), 1);
+	});
+}
+
+#[test]
+fn unresponsive_webhook_times_out() {
+	MockEnvironment::new().execute_with(|| {
+		update_registry(vec![para(Polkadot, 2000, current_timestamp() - 1)]).unwrap();
+
+		let webhook = MockWebhook::unresponsive();
+		let notifier =
+			NotifierConfig { webhook_timeout: Some(1), ..notifier(&webhook, WebhookFormat::Json) };
+
+		let start = Instant::now();
+		assert!(notify(&notifier).unwrap().is_empty());
+		assert!(start.elapsed() < Duration::from_secs(5));
+		webhook.next_request();
+
+		// Retried on the next scan.
+		assert!(sent_notifications(&notifier).unwrap().is_empty());
+	});
+}
+
+#[test]
+fn chat_payloads_work() {
+	MockEnvironment::new().execute_with(|| {
+		update_registry(vec![para(Polkadot, 2000, current_timestamp() - 1)]).unwrap();
+
+		let webhook = MockWebhook::new(200);
+		notify(&notifier(&webhook, WebhookFormat::Slack)).unwrap();
+
+		let payload: serde_json::Value = serde_json::from_str(&webhook.next_request()).unwrap();
+		let text = payload["text"].as_str().unwrap();
+		assert!(text.contains("Polkadot-2000"));
+		assert!(text.contains("expired"));
+
+		// Each notification is only sent once, regardless of the format.
+		let _ = std::fs::remove_file(config().notifier.unwrap().sent_log);
+
+		let webhook = MockWebhook::new(200);
+		notify(&notifier(&webhook, WebhookFormat::Matrix)).unwrap();
+
+		let payload: serde_json::Value = serde_json::from_str(&webhook.next_request()).unwrap();
+		assert_eq!(payload["msgtype"], "m.text");
+		assert!(payload["body"].as_str().unwrap().contains("Polkadot-2000"));
+	});
+}
+
+fn para(relay: types::RelayChain, para_id: u32, expiry_timestamp: Timestamp) -> Parachain {
+	Parachain { expiry_timestamp, ..mock_para(relay, para_id) }
+}
+
+fn notifier(webhook: &MockWebhook, format: WebhookFormat) -> NotifierConfig {
+	NotifierConfig {
+		webhook_url: webhook.url.clone(),
+		format,
+		grace_period: GRACE_PERIOD,
+		..config().notifier.expect("The mock config has a notifier")
+	}
+}
+
+/// A local stand-in for the webhook, responding to every request with the given status.
+struct MockWebhook {
+	url: String,
+	/// The bodies of the received requests.
+	requests: Receiver<String>,
+}
+
+impl MockWebhook {
+	fn new(status: u16) -> Self {
+		Self::spawn(Some(status))
+	}
+
+	/// A webhook which receives the requests but never responds to them.
+	fn unresponsive() -> Self {
+		Self::spawn(None)
+	}
+
+	fn spawn(status: Option<u16>) -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind the mock webhook");
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		let (sender, requests) = channel();
+
+		thread::spawn(move || {
+			// Kept open, so the unanswered requests don't fail before timing out.
+			let mut unanswered = vec![];
+			for mut stream in listener.incoming().filter_map(|stream| stream.ok()) {
+				let body = read_body(&mut BufReader::new(&stream));
+				// Received before responding, so the request is available once it completes.
+				if sender.send(body).is_err() {
+					return;
+				}
+
+				let Some(status) = status else {
+					unanswered.push(stream);
+					continue;
+				};
+				let response = format!(
+					"HTTP/1.1 {} Mock\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+					status
+				);
+				let _ = stream.write_all(response.as_bytes());
+			}
+		});
+
+		Self { url, requests }
+	}
+
+	fn next_request(&self) -> String {
+		self.requests.try_recv().expect("The webhook didn't receive a request")
+	}
+}
+
+fn read_body(reader: &mut impl BufRead) -> String {
+	let mut content_length = 0;
+	loop {
+		let mut line = String::new();
+		if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+			break;
+		}
+		if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+			content_length = length.trim().parse().unwrap_or(0);
+		}
+	}
+
+	let mut body = vec![0; content_length];
+	let _ = reader.read_exact(&mut body);
+	String::from_utf8_lossy(&body).into_owned()
+}
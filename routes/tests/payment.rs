@@ -0,0 +1,180 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+use shared::{
+	config::{config, Currency, PaymentInfo, Transfer},
+	extrinsic::{Call, ExtrinsicSummary},
+	payment::PaymentError,
+	watcher::payment_remark,
+};
+use types::RelayChain::*;
+
+fn payment_info() -> PaymentInfo {
+	config()
+		.payment_info_for(&Polkadot)
+		.cloned()
+		.expect("The mock config has payments")
+}
+
+fn transfer(payment_info: &PaymentInfo, currency: Currency, amount: u128) -> Transfer {
+	Transfer { currency, to: payment_info.receiver.clone(), amount }
+}
+
+#[test]
+fn asset_payment_is_accepted() {
+	let payment_info = payment_info();
+	let usdt = Currency::Asset(1984);
+
+	let transfers = [transfer(&payment_info, usdt, 1_000_000)];
+	assert_eq!(payment_info.paid(&transfers), Some((usdt, 1_000_000)));
+
+	// The transfers of the same asset add up.
+	let transfers =
+		[transfer(&payment_info, usdt, 400_000), transfer(&payment_info, usdt, 600_000)];
+	assert_eq!(payment_info.paid(&transfers), Some((usdt, 1_000_000)));
+}
+
+#[test]
+fn asset_payment_of_other_asset_is_rejected() {
+	let payment_info = payment_info();
+
+	let transfers = [transfer(&payment_info, Currency::Asset(1337), 1_000_000)];
+	assert_eq!(payment_info.paid(&transfers), None);
+}
+
+#[test]
+fn insufficient_asset_payment_is_rejected() {
+	let payment_info = payment_info();
+	let usdt = Currency::Asset(1984);
+
+	let transfers = [transfer(&payment_info, usdt, 999_999)];
+	assert_eq!(payment_info.paid(&transfers), None);
+
+	// The amounts paid in different currencies don't add up.
+	let transfers = [
+		transfer(&payment_info, usdt, 500_000),
+		transfer(&payment_info, Currency::Native, 500_000),
+	];
+	assert_eq!(payment_info.paid(&transfers), None);
+}
+
+#[test]
+fn asset_payment_to_other_account_is_rejected() {
+	let payment_info = payment_info();
+
+	let transfers = [Transfer {
+		currency: Currency::Asset(1984),
+		to: "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY".parse().unwrap(),
+		amount: 1_000_000,
+	}];
+	assert_eq!(payment_info.paid(&transfers), None);
+}
+
+/// A native payment for Polkadot 2000, made by the given call.
+fn summary(payment_info: &PaymentInfo, call: Call) -> ExtrinsicSummary {
+	let cost = payment_info.cost.parse().unwrap();
+	ExtrinsicSummary {
+		call,
+		transfers: vec![transfer(payment_info, Currency::Native, cost)],
+		succeeded: true,
+	}
+}
+
+fn remark() -> Call {
+	Call::Remark(payment_remark(&(Polkadot, 2000)))
+}
+
+/// A call dispatching the given calls, e.g. a batch, proxy or multisig call.
+fn dispatching(calls: Vec<Call>) -> Call {
+	Call::Other(calls)
+}
+
+/// Any call which doesn't dispatch other calls, e.g. `Balances::transfer_allow_death`.
+fn transfer_call() -> Call {
+	Call::Other(vec![])
+}
+
+#[test]
+fn batched_payment_is_accepted() {
+	let payment_info = payment_info();
+	let cost = payment_info.cost.parse().unwrap();
+
+	// `Utility::batch_all` of the transfer and the remark.
+	let summary = summary(&payment_info, dispatching(vec![transfer_call(), remark()]));
+	assert_eq!(summary.payment_for(&(Polkadot, 2000), &payment_info), Ok((Currency::Native, cost)));
+
+	// The remark identifies the paid parachain.
+	assert_eq!(summary.payment_for(&(Polkadot, 2004), &payment_info), Err(PaymentError::NotFound));
+	assert_eq!(summary.payment_for(&(Kusama, 2000), &payment_info), Err(PaymentError::NotFound));
+}
+
+#[test]
+fn payment_through_proxy_or_multisig_is_accepted() {
+	let payment_info = payment_info();
+	let cost = payment_info.cost.parse().unwrap();
+	let batch = || dispatching(vec![transfer_call(), remark()]);
+
+	// `Proxy::proxy` dispatching the batch.
+	let proxied = summary(&payment_info, dispatching(vec![batch()]));
+	assert_eq!(proxied.payment_for(&(Polkadot, 2000), &payment_info), Ok((Currency::Native, cost)));
+
+	// `Multisig::as_multi` dispatching the batch through a proxy.
+	let multisig = summary(&payment_info, dispatching(vec![dispatching(vec![batch()])]));
+	assert_eq!(
+		multisig.payment_for(&(Polkadot, 2000), &payment_info),
+		Ok((Currency::Native, cost))
+	);
+}
+
+#[test]
+fn payment_requires_the_remark_call() {
+	let payment_info = payment_info();
+
+	// The transfers are taken from the events, so a `transfer_allow_death` alone isn't a payment
+	// for any parachain.
+	let summary = summary(&payment_info, transfer_call());
+	assert_eq!(summary.payment_for(&(Polkadot, 2000), &payment_info), Err(PaymentError::NotFound));
+
+	// `System::remark` and `System::remark_with_event` are decoded alike, so it doesn't matter
+	// whether the remark emitted an event. A remark merely containing the remark of the parachain
+	// isn't accepted though.
+	let mut remark = payment_remark(&(Polkadot, 2000));
+	remark.push(b'1');
+	let summary = ExtrinsicSummary { call: Call::Remark(remark), ..summary };
+	assert_eq!(summary.payment_for(&(Polkadot, 2000), &payment_info), Err(PaymentError::NotFound));
+}
+
+#[test]
+fn failed_payment_is_rejected() {
+	let payment_info = payment_info();
+
+	let summary = ExtrinsicSummary {
+		succeeded: false,
+		..summary(&payment_info, dispatching(vec![transfer_call(), remark()]))
+	};
+	assert_eq!(summary.payment_for(&(Polkadot, 2000), &payment_info), Err(PaymentError::Failed));
+}
+
+#[test]
+fn insufficient_payment_is_rejected() {
+	let payment_info = payment_info();
+	let cost: u128 = payment_info.cost.parse().unwrap();
+
+	let summary = ExtrinsicSummary {
+		transfers: vec![transfer(&payment_info, Currency::Native, cost - 1)],
+		..summary(&payment_info, dispatching(vec![transfer_call(), remark()]))
+	};
+	assert_eq!(summary.payment_for(&(Polkadot, 2000), &payment_info), Err(PaymentError::NotFound));
+}
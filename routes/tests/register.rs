@@ -13,18 +13,25 @@
 // You should have received a copy of the GNU General Public License
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
-use polkadot_core_primitives::BlockNumber;
 use rocket::{
 	http::{ContentType, Status},
 	local::blocking::{Client, LocalResponse},
 	routes,
 };
-use routes::{register::register_para, Error};
-use shared::registry::{registered_para, registered_paras};
-use types::RelayChain::*;
+use routes::{
+	register::{register_para, RegistrationData},
+	Error, ErrorResponse,
+};
+use shared::{
+	chaindata::ChainDataError,
+	config::config,
+	current_timestamp,
+	registry::{registered_para, registered_paras},
+};
+use types::{RelayChain::*, Timestamp};
 
 mod mock;
-use mock::{mock_para, MockEnvironment};
+use mock::{mock_para, with_config, MockEnvironment};
 
 #[test]
 fn register_works() {
@@ -32,7 +39,8 @@ fn register_works() {
 		let rocket = rocket::build().mount("/", routes![register_para]);
 		let client = Client::tracked(rocket).expect("valid rocket instance");
 
-		let para = mock_para(Polkadot, 2001);
+		let mut para = mock_para(Polkadot, 2006);
+		let registration_data = RegistrationData { para: (Polkadot, 2006), payment: None };
 
 		let response = client
 			.post("/register_para")
@@ -42,25 +50,54 @@ fn register_works() {
 
 		assert_eq!(response.status(), Status::Ok);
 
-
-		let registered = registered_para(Polkadot, 2000).unwrap();
+		let registered = registered_para(Polkadot, 2006).unwrap().unwrap();
 
 		// Set the `expiry_timestamp` to the proper value.
 		para.expiry_timestamp = registered.expiry_timestamp;
 
 		// Ensure the parachain is properly registered:
-		assert_eq!(registered_paras(), vec![para.clone()]);
+		assert_eq!(registered_paras().unwrap(), vec![para.clone()]);
 		assert_eq!(registered, para);
 	});
 }
 
+#[test]
+fn registration_uses_payment_info_of_relay() {
+	MockEnvironment::default().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![register_para]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let register = |para| {
+			let registration_data = RegistrationData { para, payment: None };
+			client
+				.post("/register_para")
+				.header(ContentType::JSON)
+				.body(serde_json::to_string(&registration_data).unwrap())
+				.dispatch()
+				.status()
+		};
+
+		assert_eq!(register((Polkadot, 2006)), Status::Ok);
+		assert_eq!(register((Kusama, 2124)), Status::Ok);
+
+		let polkadot_duration = config().payment_info_for(&Polkadot).unwrap().subscription_duration;
+		let polkadot_para = registered_para(Polkadot, 2006).unwrap().unwrap();
+		assert!(polkadot_para.expiry_timestamp <= current_timestamp() + polkadot_duration);
+
+		// The mock config doesn't have a payment configuration for Kusama.
+		assert!(config().payment_info_for(&Kusama).is_none());
+		let kusama_para = registered_para(Kusama, 2124).unwrap().unwrap();
+		assert_eq!(kusama_para.expiry_timestamp, Timestamp::MAX);
+	});
+}
+
 #[test]
 fn cannot_register_same_para_twice() {
 	MockEnvironment::default().execute_with(|| {
 		let rocket = rocket::build().mount("/", routes![register_para]);
 		let client = Client::tracked(rocket).expect("valid rocket instance");
 
-		let para = mock_para(Polkadot, 2001);
+		let registration_data = RegistrationData { para: (Polkadot, 2006), payment: None };
 
 		let register = client
 			.post("/register_para")
@@ -69,11 +106,89 @@ fn cannot_register_same_para_twice() {
 
 		// Cannot register the same para twice:
 		assert_eq!(register.clone().dispatch().status(), Status::Ok);
-		assert_eq!(parse_err_response(register.dispatch()), Error::AlreadyRegistered);
+
+		let response = register.dispatch();
+		assert_eq!(response.status(), Status::Conflict);
+		assert_eq!(response.content_type(), Some(ContentType::JSON));
+
+		let body: ErrorResponse = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+		assert_eq!(body.code, "AlreadyRegistered");
+		assert_eq!(body.details, None);
+		assert_eq!(Error::try_from(body).unwrap(), Error::AlreadyRegistered);
+	});
+}
+
+#[test]
+fn cannot_register_unknown_para() {
+	MockEnvironment::default().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![register_para]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let registration_data = RegistrationData { para: (Polkadot, 9999), payment: None };
+
+		let response = client
+			.post("/register_para")
+			.header(ContentType::JSON)
+			.body(serde_json::to_string(&registration_data).unwrap())
+			.dispatch();
+
+		assert_eq!(response.status(), Status::NotFound);
+		assert_eq!(
+			parse_err_response(response),
+			Error::ChainDataError(ChainDataError::ParaNotFound)
+		);
+		assert!(registered_paras().unwrap().is_empty());
+	});
+}
+
+#[test]
+fn malformed_registration_rejected() {
+	MockEnvironment::default().execute_with(|| {
+		let rocket = rocket::build()
+			.mount("/", routes![register_para])
+			.register("/", routes::catchers());
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let response = client
+			.post("/register_para")
+			.header(ContentType::JSON)
+			.body(r#"{ "para": ["Polkadot", "2000"] }"#)
+			.dispatch();
+
+		assert_eq!(response.status(), Status::UnprocessableEntity);
+		assert_eq!(parse_err_response(response), Error::InvalidRequest);
+		assert!(registered_paras().unwrap().is_empty());
+	});
+}
+
+#[test]
+fn registration_requires_payment_without_free_mode() {
+	MockEnvironment::default().execute_with(|| {
+		let free_mode_off =
+			|config: String| config.replace("free_mode = true", "free_mode = false");
+		with_config(free_mode_off, || {
+			assert!(!config().free_mode);
+
+			let rocket = rocket::build().mount("/", routes![register_para]);
+			let client = Client::tracked(rocket).expect("valid rocket instance");
+
+			let registration_data = RegistrationData { para: (Polkadot, 2006), payment: None };
+
+			let response = client
+				.post("/register_para")
+				.header(ContentType::JSON)
+				.body(serde_json::to_string(&registration_data).unwrap())
+				.dispatch();
+
+			assert_eq!(response.status(), Status::PaymentRequired);
+			assert_eq!(parse_err_response(response), Error::PaymentRequired);
+			assert!(registered_para(Polkadot, 2006).unwrap().is_none());
+		});
 	});
 }
 
 fn parse_err_response<'a>(response: LocalResponse<'a>) -> Error {
 	let body = response.into_string().unwrap();
-	body.into()
+	let response: ErrorResponse = serde_json::from_str(&body).expect("can't parse value");
+	response.try_into().expect("unknown error")
 }
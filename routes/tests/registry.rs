@@ -18,10 +18,18 @@ use rocket::{
 	local::blocking::{Client, LocalResponse},
 	routes,
 };
-use routes::registry::registry;
+use routes::{
+	registry::{registry, registry_history, RegisteredPara},
+	Error, ErrorResponse,
+};
 
-use shared::chaindata::get_para;
-use types::{Parachain, RelayChain::*};
+use shared::{
+	audit::{AuditEntry, ChangeOrigin, RegistryAction},
+	chaindata::get_para,
+	config::config,
+	registry::{modify_registry, registered_paras, update_registry, RegistryError},
+};
+use types::{Parachain, RelayChain::*, Timestamp};
 
 mod mock;
 use mock::MockEnvironment;
@@ -35,18 +43,210 @@ fn getting_registry_works() {
 		let response = client.get("/registry").dispatch();
 		assert_eq!(response.status(), Status::Ok);
 
-
 		let mut registry = parse_ok_response(response);
 		registry.sort_by_key(|p| p.para_id);
 
 		assert_eq!(
 			registry,
-			vec![get_para(Polkadot, 2000).unwrap(), get_para(Polkadot, 2004).unwrap()]
+			vec![
+				get_para(Polkadot, 2000).unwrap().into(),
+				get_para(Polkadot, 2004).unwrap().into()
+			]
+		);
+		assert!(registry.iter().all(|para| !para.never_expires));
+	});
+}
+
+#[test]
+fn never_expiring_subscriptions_have_no_expiry() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![registry]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let mut para = get_para(Polkadot, 2000).unwrap();
+		para.expiry_timestamp = Timestamp::MAX;
+		update_registry(vec![para]).unwrap();
+
+		let registry = parse_ok_response(client.get("/registry").dispatch());
+		assert_eq!(registry.len(), 1);
+		assert_eq!(registry[0].expiry_timestamp, None);
+		assert!(registry[0].never_expires);
+	});
+}
+
+#[test]
+fn concurrent_registry_updates_are_not_lost() {
+	MockEnvironment::new().execute_with(|| {
+		let para = get_para(Polkadot, 2000).unwrap();
+
+		let handles: Vec<_> = (0..10)
+			.map(|i| {
+				let para = para.clone();
+				std::thread::spawn(move || {
+					modify_registry(&origin(), |paras| -> Result<(), RegistryError> {
+						let mut para = para;
+						para.para_id = 10_000 + i;
+						paras.push(para);
+						Ok(())
+					})
+				})
+			})
+			.collect();
+
+		for handle in handles {
+			assert!(handle.join().unwrap().is_ok());
+		}
+
+		let mut registered: Vec<u32> =
+			registered_paras().unwrap().into_iter().map(|p| p.para_id).collect();
+		registered.sort();
+		assert_eq!(registered, [vec![2000, 2004], (10_000..10_010).collect()].concat());
+	});
+}
+
+#[test]
+fn failed_registry_update_is_discarded() {
+	MockEnvironment::new().execute_with(|| {
+		let registered = registered_paras().unwrap();
+
+		let result = modify_registry(&origin(), |paras| -> Result<(), RegistryError> {
+			paras.clear();
+			Err(RegistryError::InvalidRegistry)
+		});
+		assert_eq!(result, Err(RegistryError::InvalidRegistry));
+		assert_eq!(registered_paras().unwrap(), registered);
+	});
+}
+
+#[test]
+fn invalid_registry_is_handled() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![registry]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		std::fs::write(config().registry, b"[{").unwrap();
+
+		let response = client.get("/registry").dispatch();
+		assert_eq!(response.status(), Status::InternalServerError);
+
+		let body: ErrorResponse = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+		assert_eq!(body.code, "RegistryError");
+		assert_eq!(body.details, Some("InvalidRegistry".into()));
+		assert_eq!(
+			Error::try_from(body).unwrap(),
+			Error::RegistryError(RegistryError::InvalidRegistry)
 		);
 	});
 }
 
-fn parse_ok_response<'a>(response: LocalResponse<'a>) -> Vec<Parachain> {
+#[test]
+fn registry_history_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![registry_history]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let mut para = get_para(Polkadot, 2000).unwrap();
+		para.para_id = 3000;
+		para.expiry_timestamp = 100;
+
+		let paid = ChangeOrigin { actor: "127.0.0.1".into(), payment_block_number: Some(42) };
+		let modify = |origin: &ChangeOrigin, f: &dyn Fn(&mut Vec<Parachain>)| {
+			modify_registry(origin, |paras| -> Result<(), RegistryError> {
+				f(paras);
+				Ok(())
+			})
+			.unwrap();
+		};
+
+		modify(&paid, &|paras| paras.push(para.clone()));
+		modify(&paid, &|paras| paras.last_mut().unwrap().expiry_timestamp = 200);
+		modify(&origin(), &|paras| paras.last_mut().unwrap().rpcs = vec!["wss://rpc".into()]);
+		modify(&origin(), &|paras| {
+			paras.pop();
+		});
+		// Changes to other parachains don't show up in the history.
+		modify(&origin(), &|paras| paras[0].expiry_timestamp = 1);
+
+		let response = client.get("/registry/polkadot/3000/history").dispatch();
+		assert_eq!(response.status(), Status::Ok);
+
+		let history: Vec<AuditEntry> =
+			serde_json::from_str(&response.into_string().unwrap()).expect("can't parse value");
+		let summary: Vec<_> = history
+			.iter()
+			.map(|entry| {
+				(
+					entry.action.clone(),
+					entry.actor.as_str(),
+					entry.payment_block_number,
+					entry.old_expiry,
+					entry.new_expiry,
+				)
+			})
+			.collect();
+
+		assert_eq!(
+			summary,
+			vec![
+				(RegistryAction::Registration, "127.0.0.1", Some(42), None, Some(100)),
+				(
+					RegistryAction::SubscriptionExtension,
+					"127.0.0.1",
+					Some(42),
+					Some(100),
+					Some(200)
+				),
+				(RegistryAction::RpcChange, "admin", None, Some(200), Some(200)),
+				(RegistryAction::Removal, "admin", None, Some(200), None),
+			]
+		);
+		assert!(history.iter().all(|entry| entry.para == (Polkadot, 3000)));
+
+		// A para without any changes has an empty history.
+		let response = client.get("/registry/polkadot/3001/history").dispatch();
+		assert_eq!(response.into_string().unwrap(), "[]");
+
+		// No para can be registered on an unknown relay chain.
+		let response = client.get("/registry/foo/3000/history").dispatch();
+		assert_eq!(response.status(), Status::NotFound);
+		let body: ErrorResponse = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+		assert_eq!(Error::try_from(body).unwrap(), Error::NotRegistered);
+	});
+}
+
+#[test]
+fn registry_is_restored_if_audit_fails() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![registry_history]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let registered = registered_paras().unwrap();
+		// Neither readable nor writable as a file.
+		std::fs::create_dir(config().audit_log).unwrap();
+
+		let result = modify_registry(&origin(), |paras| -> Result<(), RegistryError> {
+			paras.clear();
+			Ok(())
+		});
+		assert_eq!(result, Err(RegistryError::AuditFailed));
+		assert_eq!(registered_paras().unwrap(), registered);
+
+		let response = client.get("/registry/polkadot/2000/history").dispatch();
+		assert_eq!(response.status(), Status::InternalServerError);
+
+		let body: ErrorResponse = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+		assert_eq!(
+			Error::try_from(body).unwrap(),
+			Error::RegistryError(RegistryError::AuditFailed)
+		);
+	});
+}
+
+fn origin() -> ChangeOrigin {
+	ChangeOrigin { actor: "admin".into(), payment_block_number: None }
+}
+
+fn parse_ok_response<'a>(response: LocalResponse<'a>) -> Vec<RegisteredPara> {
 	let body = response.into_string().unwrap();
 	serde_json::from_str(&body).expect("can't parse value")
 }
@@ -0,0 +1,146 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::Decode;
+use rocket::{
+	http::Status,
+	local::blocking::{Client, LocalResponse},
+	routes,
+};
+use routes::{
+	subscription::{subscription, SubscriptionStatus},
+	Error, ErrorResponse,
+};
+use shared::{
+	config::config, current_timestamp, registry::update_registry, watcher::remarked_para,
+};
+use types::{RelayChain::*, Timestamp};
+
+mod mock;
+use mock::{mock_para, MockEnvironment};
+
+#[test]
+fn subscription_status_works() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![subscription]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let payment_info = config().payment_info_for(&Polkadot).cloned().unwrap();
+		let mut para = mock_para(Polkadot, 2000);
+		para.expiry_timestamp = current_timestamp() + payment_info.renewal_period + 1000;
+		update_registry(vec![para.clone()]).unwrap();
+
+		let status = parse_ok_response(client.get("/subscription/polkadot/2000").dispatch());
+		assert!(status.registered);
+		assert_eq!(status.expiry_timestamp, Some(para.expiry_timestamp));
+		assert!(!status.never_expires);
+		assert!(!status.renewal_open);
+		let opens_in = status.renewal_opens_in.unwrap();
+		assert!(opens_in > 990 && opens_in <= 1000);
+
+		let quote = status.payment.unwrap();
+		assert_eq!(quote.receiver, payment_info.receiver.to_string());
+		assert_eq!(quote.cost, payment_info.cost);
+		assert_eq!(quote.subscription_duration, payment_info.subscription_duration);
+		assert_eq!(quote.assets.len(), payment_info.assets.len());
+		assert_eq!(quote.plans[0].name, payment_info.plans[0].name);
+		assert_eq!(quote.remark, "regionx-weigher::Polkadot:2000");
+
+		// The encoded call is `System::remark_with_event` making the remark.
+		assert!(quote.remark_call.starts_with("0x0007"));
+		let call: Vec<u8> = (2..quote.remark_call.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&quote.remark_call[i..i + 2], 16).unwrap())
+			.collect();
+		let remark = Vec::<u8>::decode(&mut &call[2..]).unwrap();
+		assert_eq!(remarked_para(&remark), Some((Polkadot, 2000)));
+	});
+}
+
+#[test]
+fn renewal_window_is_open_before_expiry() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![subscription]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let mut para = mock_para(Polkadot, 2000);
+		para.expiry_timestamp = current_timestamp() + 10;
+		update_registry(vec![para]).unwrap();
+
+		let status = parse_ok_response(client.get("/subscription/polkadot/2000").dispatch());
+		assert!(status.renewal_open);
+		assert_eq!(status.renewal_opens_in, Some(0));
+	});
+}
+
+#[test]
+fn never_expiring_subscription_has_no_expiry() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![subscription]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let mut para = mock_para(Polkadot, 2000);
+		para.expiry_timestamp = Timestamp::MAX;
+		update_registry(vec![para]).unwrap();
+
+		let status = parse_ok_response(client.get("/subscription/polkadot/2000").dispatch());
+		assert!(status.registered);
+		assert_eq!(status.expiry_timestamp, None);
+		assert!(status.never_expires);
+		assert!(!status.renewal_open);
+		assert_eq!(status.renewal_opens_in, None);
+	});
+}
+
+#[test]
+fn unregistered_para_can_be_paid_for() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![subscription]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		let status = parse_ok_response(client.get("/subscription/polkadot/2006").dispatch());
+		assert!(!status.registered);
+		assert_eq!(status.expiry_timestamp, None);
+		assert!(status.renewal_open);
+		assert_eq!(status.payment.unwrap().remark, "regionx-weigher::Polkadot:2006");
+	});
+}
+
+#[test]
+fn no_payment_without_payment_info() {
+	MockEnvironment::new().execute_with(|| {
+		let rocket = rocket::build().mount("/", routes![subscription]);
+		let client = Client::tracked(rocket).expect("valid rocket instance");
+
+		// The mock config doesn't have a payment configuration for Kusama.
+		let status = parse_ok_response(client.get("/subscription/kusama/2124").dispatch());
+		assert!(!status.renewal_open);
+		assert_eq!(status.renewal_opens_in, None);
+		assert_eq!(status.payment, None);
+
+		// No para can be registered on an unknown relay chain.
+		let response = client.get("/subscription/foo/2000").dispatch();
+		assert_eq!(response.status(), Status::NotFound);
+		let body: ErrorResponse = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+		assert_eq!(Error::try_from(body).unwrap(), Error::NotRegistered);
+	});
+}
+
+fn parse_ok_response<'a>(response: LocalResponse<'a>) -> SubscriptionStatus {
+	assert_eq!(response.status(), Status::Ok);
+
+	let body = response.into_string().unwrap();
+	serde_json::from_str(&body).expect("can't parse value")
+}
@@ -0,0 +1,478 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::Encode;
+use shared::{
+	audit::{para_history, RegistryAction},
+	chaindata::ChainDataError,
+	config::{config, Currency, PaymentInfo},
+	current_timestamp,
+	ledger::{is_used, PaymentId, PaymentReceipt},
+	payment::PaymentError,
+	registry::{registered_para, registered_paras, update_registry, RegistryError},
+	watcher::{
+		apply_payment, follow_payments, last_processed_block, payment_remark, remarked_para,
+		retry_delay, DetectedPayment, PaymentChain, WatcherError,
+	},
+};
+use std::{
+	collections::{HashMap, VecDeque},
+	future::Future,
+	sync::Mutex,
+	time::Duration,
+};
+use types::{ParaId, RelayChain, RelayChain::*};
+
+mod mock;
+use mock::{mock_para, MockEnvironment};
+
+#[test]
+fn remarked_para_works() {
+	assert_eq!(remarked_para(&payment_remark(&(Kusama, 2023))), Some((Kusama, 2023)));
+	assert_eq!(remarked_para(b"regionx-weigher::Polkadot:2000"), Some((Polkadot, 2000)));
+	assert_eq!(remarked_para(b"regionx-weigher::polkadot:2000"), Some((Polkadot, 2000)));
+
+	// Not a remark of a parachain.
+	assert_eq!(remarked_para(b"regionx-weigher::Westend:2000"), None);
+	assert_eq!(remarked_para(b"regionx-weigher::Polkadot:"), None);
+	assert_eq!(remarked_para(b"regionx-weigher::Polkadot:2000x"), None);
+	// The remark has to be exactly the one of the parachain.
+	assert_eq!(remarked_para(b"xregionx-weigher::Polkadot:2000"), None);
+	assert_eq!(remarked_para(&encoded("regionx-weigher::Polkadot:2000")), None);
+}
+
+#[test]
+fn payment_registers_unregistered_para() {
+	MockEnvironment::new().execute_with(|| {
+		let payment = mock_payment((Polkadot, 2006), 1);
+
+		let para = apply_payment(payment.clone(), &payment_info()).unwrap();
+
+		let mut expected = mock_para(Polkadot, 2006);
+		expected.expiry_timestamp = para.expiry_timestamp;
+		assert_eq!(para, expected);
+		assert!(para.expiry_timestamp >= current_timestamp() + duration() - 1);
+		assert_eq!(registered_para(Polkadot, 2006).unwrap(), Some(para));
+		assert!(is_used(&payment.receipt.payment).unwrap());
+
+		let history = para_history(Polkadot, 2006).unwrap();
+		assert_eq!(history.len(), 1);
+		assert_eq!(history[0].action, RegistryAction::Registration);
+		assert_eq!(history[0].actor, "payment-watcher");
+		assert_eq!(history[0].payment_block_number, Some(1));
+	});
+}
+
+#[test]
+fn payment_extends_registered_para() {
+	MockEnvironment::new().execute_with(|| {
+		let mut para = mock_para(Polkadot, 2000);
+		let expiry = current_timestamp() + 5000;
+		para.expiry_timestamp = expiry;
+		update_registry(vec![para.clone()]).unwrap();
+
+		let extended = apply_payment(mock_payment((Polkadot, 2000), 1), &payment_info()).unwrap();
+		assert_eq!(extended.expiry_timestamp, expiry + duration());
+		assert_eq!(registered_paras().unwrap(), vec![extended]);
+
+		let history = para_history(Polkadot, 2000).unwrap();
+		assert_eq!(history.last().unwrap().action, RegistryAction::SubscriptionExtension);
+		assert_eq!(history.last().unwrap().actor, "payment-watcher");
+	});
+}
+
+#[test]
+fn payment_extends_expired_subscription_from_now() {
+	MockEnvironment::new().execute_with(|| {
+		let mut para = mock_para(Polkadot, 2000);
+		para.expiry_timestamp = 0;
+		update_registry(vec![para]).unwrap();
+
+		let extended = apply_payment(mock_payment((Polkadot, 2000), 1), &payment_info()).unwrap();
+		assert!(extended.expiry_timestamp >= current_timestamp() + duration() - 1);
+	});
+}
+
+#[test]
+fn payment_buys_multiple_periods() {
+	MockEnvironment::new().execute_with(|| {
+		let mut payment = mock_payment((Polkadot, 2006), 1);
+		// Three periods, with the remainder not covering a fourth one.
+		payment.receipt.amount = payment.receipt.amount * 3 + 1;
+
+		let para = apply_payment(payment, &payment_info()).unwrap();
+		assert!(para.expiry_timestamp >= current_timestamp() + 3 * duration() - 1);
+		assert!(para.expiry_timestamp <= current_timestamp() + 3 * duration());
+		assert_eq!(para.plan, None);
+	});
+}
+
+#[test]
+fn payment_buys_plan() {
+	MockEnvironment::new().execute_with(|| {
+		let plan = payment_info().plans[0].clone();
+		let mut payment = mock_payment((Polkadot, 2006), 1);
+		payment.receipt.amount = plan.cost.parse().unwrap();
+
+		let para = apply_payment(payment, &payment_info()).unwrap();
+		assert!(para.expiry_timestamp >= current_timestamp() + plan.duration - 1);
+		assert_eq!(para.plan, Some(plan.name.clone()));
+
+		// Paying for single periods leaves the plan.
+		let para = apply_payment(mock_payment((Polkadot, 2006), 2), &payment_info()).unwrap();
+		assert_eq!(para.plan, None);
+	});
+}
+
+#[test]
+fn asset_payment_works() {
+	MockEnvironment::new().execute_with(|| {
+		let asset = payment_info().assets[0].clone();
+		let asset_cost: u128 = asset.cost.parse().unwrap();
+
+		let mut payment = mock_payment((Polkadot, 2006), 1);
+		payment.receipt.currency = Currency::Asset(asset.asset_id);
+		payment.receipt.amount = 2 * asset_cost;
+
+		let para = apply_payment(payment, &payment_info()).unwrap();
+		assert!(para.expiry_timestamp >= current_timestamp() + 2 * duration() - 1);
+
+		// Plans have their own cost in assets.
+		let plan = payment_info().plans[0].clone();
+		let mut payment = mock_payment((Polkadot, 2006), 2);
+		payment.receipt.currency = Currency::Asset(asset.asset_id);
+		payment.receipt.amount = plan.asset_costs[0].cost.parse().unwrap();
+
+		let para = apply_payment(payment, &payment_info()).unwrap();
+		assert_eq!(para.plan, Some(plan.name));
+
+		// Assets which aren't accepted can't be used as payment.
+		let mut payment = mock_payment((Polkadot, 2006), 3);
+		payment.receipt.currency = Currency::Asset(asset.asset_id + 1);
+		payment.receipt.amount = asset_cost;
+
+		assert_eq!(apply_payment(payment, &payment_info()), Err(WatcherError::InsufficientPayment));
+	});
+}
+
+#[test]
+fn insufficient_payment_is_not_used() {
+	MockEnvironment::new().execute_with(|| {
+		let mut payment = mock_payment((Polkadot, 2006), 1);
+		payment.receipt.amount -= 1;
+
+		assert_eq!(
+			apply_payment(payment.clone(), &payment_info()),
+			Err(WatcherError::InsufficientPayment)
+		);
+		assert_eq!(registered_para(Polkadot, 2006).unwrap(), None);
+		assert!(!is_used(&payment.receipt.payment).unwrap());
+	});
+}
+
+#[test]
+fn cannot_apply_payment_twice() {
+	MockEnvironment::new().execute_with(|| {
+		let payment = mock_payment((Polkadot, 2000), 1);
+		let para = apply_payment(payment.clone(), &payment_info()).unwrap();
+
+		assert_eq!(
+			apply_payment(payment.clone(), &payment_info()),
+			Err(WatcherError::Payment(PaymentError::AlreadyUsed))
+		);
+		// The same payment can't be used for another parachain either.
+		let other_para = DetectedPayment { para: (Polkadot, 2004), ..payment };
+		assert_eq!(
+			apply_payment(other_para, &payment_info()),
+			Err(WatcherError::Payment(PaymentError::AlreadyUsed))
+		);
+
+		assert_eq!(registered_para(Polkadot, 2000).unwrap(), Some(para));
+	});
+}
+
+#[test]
+fn payment_for_unknown_para_is_not_used() {
+	MockEnvironment::new().execute_with(|| {
+		let paras = registered_paras().unwrap();
+		let payment = mock_payment((Polkadot, 9999), 1);
+
+		assert_eq!(
+			apply_payment(payment.clone(), &payment_info()),
+			Err(WatcherError::ChainData(ChainDataError::ParaNotFound))
+		);
+
+		assert_eq!(registered_paras().unwrap(), paras);
+		assert!(!is_used(&payment.receipt.payment).unwrap());
+	});
+}
+
+#[test]
+fn payment_is_not_used_if_registry_write_fails() {
+	MockEnvironment::new().execute_with(|| {
+		let paras = registered_paras().unwrap();
+		let payment = mock_payment((Polkadot, 2006), 1);
+
+		// Renaming the temporary file into place fails if it is a directory.
+		let tmp = format!("{}.tmp", config().registry);
+		std::fs::create_dir(&tmp).unwrap();
+
+		assert_eq!(
+			apply_payment(payment.clone(), &payment_info()),
+			Err(WatcherError::Registry(RegistryError::WriteFailed))
+		);
+		assert_eq!(registered_paras().unwrap(), paras);
+		assert!(!is_used(&payment.receipt.payment).unwrap());
+
+		// The payment can still be used once the registry is writable again.
+		std::fs::remove_dir(&tmp).unwrap();
+		assert!(apply_payment(payment.clone(), &payment_info()).is_ok());
+		assert!(is_used(&payment.receipt.payment).unwrap());
+	});
+}
+
+#[test]
+fn registry_is_reverted_if_payment_cannot_be_recorded() {
+	MockEnvironment::new().execute_with(|| {
+		let paras = registered_paras().unwrap();
+		let payment = mock_payment((Polkadot, 2006), 1);
+
+		std::fs::create_dir(config().payment_ledger).unwrap();
+
+		assert_eq!(
+			apply_payment(payment, &payment_info()),
+			Err(WatcherError::Payment(PaymentError::ValidationFailed))
+		);
+		assert_eq!(registered_paras().unwrap(), paras);
+		// The reverted registration isn't recorded.
+		assert!(para_history(Polkadot, 2006).unwrap().is_empty());
+	});
+}
+
+#[test]
+fn payment_is_released_if_changes_cannot_be_recorded() {
+	MockEnvironment::new().execute_with(|| {
+		let paras = registered_paras().unwrap();
+		let payment = mock_payment((Polkadot, 2006), 1);
+
+		// Neither readable nor writable as a file.
+		std::fs::create_dir(config().audit_log).unwrap();
+
+		assert_eq!(
+			apply_payment(payment.clone(), &payment_info()),
+			Err(WatcherError::Registry(RegistryError::AuditFailed))
+		);
+		assert_eq!(registered_paras().unwrap(), paras);
+		assert!(!is_used(&payment.receipt.payment).unwrap());
+
+		// The payment can still be used once the audit log is writable again.
+		std::fs::remove_dir(config().audit_log).unwrap();
+		assert!(apply_payment(payment.clone(), &payment_info()).is_ok());
+		assert!(is_used(&payment.receipt.payment).unwrap());
+		assert_eq!(para_history(Polkadot, 2006).unwrap().len(), 1);
+	});
+}
+
+#[test]
+fn watcher_starts_at_latest_finalized_block() {
+	MockEnvironment::new().execute_with(|| {
+		let mut chain = MockChain::new(5, vec![Ok(7)]);
+		// Made before the watcher was started, so it isn't applied.
+		chain.payments.insert(3, vec![mock_payment((Polkadot, 2006), 1)]);
+		chain.payments.insert(6, vec![mock_payment((Polkadot, 2007), 1)]);
+
+		assert_eq!(follow(&mut chain), Ok(()));
+
+		assert_eq!(chain.queried(), vec![5, 6, 7]);
+		assert_eq!(last_processed_block(&Polkadot), Ok(Some(7)));
+		assert_eq!(registered_para(Polkadot, 2006).unwrap(), None);
+		assert!(registered_para(Polkadot, 2007).unwrap().is_some());
+	});
+}
+
+#[test]
+fn watcher_backfills_missed_blocks_after_reconnecting() {
+	MockEnvironment::new().execute_with(|| {
+		let mut chain = MockChain::new(2, vec![Ok(3), Err(WatcherError::Rpc), Ok(4)]);
+		assert_eq!(follow(&mut chain), Err(WatcherError::Rpc));
+		assert_eq!(chain.queried(), vec![2, 3]);
+		assert_eq!(last_processed_block(&Polkadot), Ok(Some(3)));
+
+		// Blocks 4 and 5 got finalized while the watcher was disconnected.
+		let mut chain = MockChain::new(6, vec![Ok(8)]);
+		chain.payments.insert(5, vec![mock_payment((Polkadot, 2006), 1)]);
+		assert_eq!(follow(&mut chain), Ok(()));
+
+		assert_eq!(chain.queried(), vec![4, 5, 6, 7, 8]);
+		assert_eq!(last_processed_block(&Polkadot), Ok(Some(8)));
+		assert!(registered_para(Polkadot, 2006).unwrap().is_some());
+	});
+}
+
+#[test]
+fn watcher_retries_block_which_failed_to_load() {
+	MockEnvironment::new().execute_with(|| {
+		let payment = mock_payment((Polkadot, 2006), 1);
+
+		let mut chain = MockChain::new(2, vec![Ok(5)]);
+		chain.payments.insert(4, vec![payment.clone()]);
+		chain.unavailable = Some(4);
+		assert_eq!(follow(&mut chain), Err(WatcherError::Rpc));
+		assert_eq!(last_processed_block(&Polkadot), Ok(Some(3)));
+		assert!(!is_used(&payment.receipt.payment).unwrap());
+
+		chain.unavailable = None;
+		chain.notifications = vec![Ok(5)].into();
+		assert_eq!(follow(&mut chain), Ok(()));
+		assert_eq!(last_processed_block(&Polkadot), Ok(Some(5)));
+		assert!(is_used(&payment.receipt.payment).unwrap());
+	});
+}
+
+#[test]
+fn watcher_retries_block_with_payment_which_failed_to_apply() {
+	MockEnvironment::new().execute_with(|| {
+		let payment = mock_payment((Polkadot, 2006), 1);
+		let mut insufficient = mock_payment((Polkadot, 2007), 2);
+		insufficient.receipt.amount -= 1;
+
+		let mut chain = MockChain::new(2, vec![Ok(4)]);
+		chain.payments.insert(3, vec![insufficient, payment.clone()]);
+		// Neither readable nor writable as a file.
+		std::fs::create_dir(config().payment_ledger).unwrap();
+
+		// The insufficient payment is skipped, while the ledger failure is retried.
+		assert_eq!(follow(&mut chain), Err(WatcherError::Payment(PaymentError::ValidationFailed)));
+		assert_eq!(last_processed_block(&Polkadot), Ok(Some(2)));
+		assert_eq!(registered_para(Polkadot, 2006).unwrap(), None);
+
+		std::fs::remove_dir(config().payment_ledger).unwrap();
+		chain.notifications = vec![Ok(4)].into();
+		assert_eq!(follow(&mut chain), Ok(()));
+		assert_eq!(last_processed_block(&Polkadot), Ok(Some(4)));
+		assert!(is_used(&payment.receipt.payment).unwrap());
+		assert!(registered_para(Polkadot, 2006).unwrap().is_some());
+		assert_eq!(registered_para(Polkadot, 2007).unwrap(), None);
+	});
+}
+
+#[test]
+fn watcher_ignores_payments_for_other_relay_chains() {
+	MockEnvironment::new().execute_with(|| {
+		let mut chain = MockChain::new(1, vec![]);
+		chain.payments.insert(1, vec![mock_payment((Kusama, 2023), 1)]);
+
+		assert_eq!(follow(&mut chain), Ok(()));
+		assert_eq!(registered_para(Kusama, 2023).unwrap(), None);
+		assert_eq!(last_processed_block(&Polkadot), Ok(Some(1)));
+	});
+}
+
+#[test]
+fn retry_delay_backs_off_exponentially() {
+	let delays: Vec<_> = (0..8).map(|attempt| retry_delay(attempt).as_secs()).collect();
+	assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+	assert_eq!(retry_delay(u32::MAX), Duration::from_secs(60));
+}
+
+/// A payment chain serving the payments from memory.
+struct MockChain {
+	finalized: u32,
+	/// The finalized blocks yielded by the subscription.
+	notifications: VecDeque<Result<u32, WatcherError>>,
+	payments: HashMap<u32, Vec<DetectedPayment>>,
+	/// A block which fails to load.
+	unavailable: Option<u32>,
+	queried: Mutex<Vec<u32>>,
+}
+
+impl MockChain {
+	fn new(finalized: u32, notifications: Vec<Result<u32, WatcherError>>) -> Self {
+		MockChain {
+			finalized,
+			notifications: notifications.into(),
+			payments: HashMap::new(),
+			unavailable: None,
+			queried: Mutex::new(vec![]),
+		}
+	}
+
+	fn queried(&self) -> Vec<u32> {
+		self.queried.lock().unwrap().clone()
+	}
+}
+
+impl PaymentChain for MockChain {
+	fn finalized_block(&self) -> impl Future<Output = Result<u32, WatcherError>> + Send {
+		std::future::ready(Ok(self.finalized))
+	}
+
+	fn next_finalized_block(
+		&mut self,
+	) -> impl Future<Output = Option<Result<u32, WatcherError>>> + Send {
+		std::future::ready(self.notifications.pop_front())
+	}
+
+	fn payments_at(
+		&self,
+		block_number: u32,
+	) -> impl Future<Output = Result<Vec<DetectedPayment>, WatcherError>> + Send {
+		self.queried.lock().unwrap().push(block_number);
+
+		std::future::ready(if self.unavailable == Some(block_number) {
+			Err(WatcherError::Rpc)
+		} else {
+			Ok(self.payments.get(&block_number).cloned().unwrap_or_default())
+		})
+	}
+}
+
+fn follow(chain: &mut MockChain) -> Result<(), WatcherError> {
+	let runtime = rocket::tokio::runtime::Runtime::new().unwrap();
+	runtime.block_on(follow_payments(chain, &Polkadot, &payment_info()))
+}
+
+fn payment_info() -> PaymentInfo {
+	config()
+		.payment_info_for(&Polkadot)
+		.cloned()
+		.expect("Payments are enabled for Polkadot")
+}
+
+fn duration() -> u64 {
+	payment_info().subscription_duration
+}
+
+/// Encodes the remark the way it is encoded within an extrinsic.
+fn encoded(remark: &str) -> Vec<u8> {
+	remark.as_bytes().to_vec().encode()
+}
+
+fn mock_payment(para: (RelayChain, ParaId), extrinsic_index: u32) -> DetectedPayment {
+	DetectedPayment {
+		para,
+		receipt: PaymentReceipt {
+			payment: PaymentId {
+				chain: "0x91b1".into(),
+				block_hash: "0x1234".into(),
+				extrinsic_index,
+			},
+			block_number: 1,
+			extrinsic_hash: "0xabcd".into(),
+			amount: payment_info().cost.parse().unwrap(),
+			currency: Currency::Native,
+		},
+	}
+}
@@ -0,0 +1,183 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compressed monthly archives of the processed consumption data.
+//!
+//! Once a month is over the consumption of its blocks is moved out of the live file of the
+//! parachain into a gzip compressed archive, e.g. `out/Polkadot-2000/2024-03.csv.gz`. The
+//! archives are stored in the same format as the live files.
+
+use crate::{
+	config::output_directory,
+	consumption::{consumption_content, output_file_path, rows, stored, Row, StoredConsumption},
+	write_atomically, LOG_TARGET,
+};
+use chrono::NaiveDateTime;
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use std::{
+	collections::{BTreeMap, HashSet},
+	fs::File,
+	io::{ErrorKind, Write},
+};
+use types::{Parachain, Timestamp, WeightConsumption};
+
+const ARCHIVE_EXTENSION: &str = ".csv.gz";
+
+/// Moves the consumption of all the months preceding the one `now` belongs to from the live file
+/// into the archives.
+pub fn rotate_consumption(para: Parachain, now: Timestamp) -> Result<(), std::io::Error> {
+	let Some(current_month) = month(now) else { return Ok(()) };
+
+	let path = output_file_path(para.clone(), None);
+	let live = stored(rows(File::open(&path)?)?.collect());
+
+	let mut completed: BTreeMap<String, Vec<WeightConsumption>> = BTreeMap::new();
+	let mut current = vec![];
+	for entry in live.entries {
+		match month(entry.timestamp) {
+			Some(month) if month < current_month => completed.entry(month).or_default().push(entry),
+			_ => current.push(entry),
+		}
+	}
+
+	if completed.is_empty() {
+		return Ok(());
+	}
+
+	for (month, entries) in completed {
+		log::info!(
+			target: LOG_TARGET,
+			"{}-{} - Archiving weight consumption of {}.",
+			para.relay_chain, para.para_id, month
+		);
+
+		let mut archived = match read_archive(para.clone(), &month) {
+			Ok(archived) => archived.entries,
+			Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+			Err(e) => return Err(e),
+		};
+
+		let blocks: HashSet<u32> = archived.iter().map(|entry| entry.block_number).collect();
+		archived.extend(entries.into_iter().filter(|entry| !blocks.contains(&entry.block_number)));
+		archived.sort_by_key(|entry| entry.block_number);
+
+		write_archive(para.clone(), &month, &archived)?;
+	}
+
+	// The live file is only updated once all the data is archived, so nothing gets lost if the
+	// rotation is interrupted.
+	write_atomically(&path, &consumption_content(&current)?)
+}
+
+/// Returns the months for which there is archived consumption, in chronological order.
+pub fn archived_months(para: Parachain) -> Vec<String> {
+	let Ok(entries) = std::fs::read_dir(archive_directory(para)) else { return vec![] };
+
+	let mut months: Vec<String> = entries
+		.filter_map(|entry| entry.ok())
+		.filter_map(|entry| {
+			entry.file_name().to_str()?.strip_suffix(ARCHIVE_EXTENSION).map(String::from)
+		})
+		.collect();
+	months.sort();
+
+	months
+}
+
+/// Lazily reads the rows of the archives of the months overlapping with `start` and `end`, in
+/// chronological order.
+///
+/// The archives are opened upfront, while their rows are only decompressed as the returned
+/// iterator advances.
+pub(crate) fn archived_rows(
+	para: Parachain,
+	start: Timestamp,
+	end: Timestamp,
+) -> Result<impl Iterator<Item = Row> + Send, std::io::Error> {
+	let (first, last) = (month(start), month(end));
+
+	let archives = archived_months(para.clone())
+		.into_iter()
+		// Only the archives overlapping with the range need to be read.
+		.filter(|month| {
+			first.as_ref().is_none_or(|first| month >= first) &&
+				last.as_ref().is_none_or(|last| month <= last)
+		})
+		.map(|month| archive_rows(para.clone(), &month))
+		.collect::<Result<Vec<_>, _>>()?;
+
+	Ok(archives.into_iter().flatten())
+}
+
+/// Removes the archived consumption of all the blocks that were produced before `cutoff`.
+pub(crate) fn prune_archives(para: Parachain, cutoff: Timestamp) -> Result<(), std::io::Error> {
+	let Some(cutoff_month) = month(cutoff) else { return Ok(()) };
+
+	for month in archived_months(para.clone()) {
+		if month < cutoff_month {
+			std::fs::remove_file(archive_path(para.clone(), &month))?;
+		} else if month == cutoff_month {
+			let entries: Vec<WeightConsumption> = read_archive(para.clone(), &month)?
+				.entries
+				.into_iter()
+				.filter(|entry| entry.timestamp >= cutoff)
+				.collect();
+
+			write_archive(para.clone(), &month, &entries)?;
+		}
+	}
+
+	Ok(())
+}
+
+fn read_archive(para: Parachain, month: &str) -> Result<StoredConsumption, std::io::Error> {
+	Ok(stored(archive_rows(para, month)?.collect()))
+}
+
+fn archive_rows(
+	para: Parachain,
+	month: &str,
+) -> Result<impl Iterator<Item = Row> + Send, std::io::Error> {
+	rows(MultiGzDecoder::new(File::open(archive_path(para, month))?))
+}
+
+fn write_archive(
+	para: Parachain,
+	month: &str,
+	entries: &[WeightConsumption],
+) -> Result<(), std::io::Error> {
+	std::fs::create_dir_all(archive_directory(para.clone()))?;
+
+	let mut encoder = GzEncoder::new(vec![], Compression::default());
+	encoder.write_all(&consumption_content(entries)?)?;
+
+	write_atomically(&archive_path(para, month), &encoder.finish()?)
+}
+
+/// Returns the month to which the timestamp belongs, formatted as `YYYY-MM`.
+fn month(timestamp: Timestamp) -> Option<String> {
+	let seconds = i64::try_from(timestamp / 1000).ok()?;
+
+	NaiveDateTime::from_timestamp_opt(seconds, 0)
+		.map(|datetime| datetime.format("%Y-%m").to_string())
+}
+
+fn archive_directory(para: Parachain) -> String {
+	format!("{}/{}-{}", output_directory(None), para.relay_chain, para.para_id)
+}
+
+fn archive_path(para: Parachain, month: &str) -> String {
+	format!("{}/{}{}", archive_directory(para), month, ARCHIVE_EXTENSION)
+}
@@ -0,0 +1,162 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Append-only audit log of all the changes made to the registry.
+//!
+//! Each line of the log is a JSON encoded [`AuditEntry`].
+
+use crate::{config::config, current_timestamp, LOG_TARGET};
+use polkadot_core_primitives::BlockNumber;
+use serde::{Deserialize, Serialize};
+use std::{
+	fs::OpenOptions,
+	io::{ErrorKind, Write},
+};
+use types::{ParaId, Parachain, RelayChain, Timestamp};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistryAction {
+	/// The parachain got registered.
+	Registration,
+	/// The expiry of the subscription got later.
+	SubscriptionExtension,
+	/// The expiry of the subscription got earlier, e.g. an admin ended the subscription.
+	SubscriptionReduction,
+	/// The RPC list or the name of the parachain changed.
+	RpcChange,
+	/// The parachain got removed from the registry.
+	Removal,
+}
+
+/// Describes who made a change to the registry and what justified it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeOrigin {
+	/// Who made the change, e.g. the IP address of the caller.
+	pub actor: String,
+	/// The block containing the payment that justified the change, if any.
+	pub payment_block_number: Option<BlockNumber>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+	/// The parachain which got changed.
+	pub para: (RelayChain, ParaId),
+	pub action: RegistryAction,
+	/// Who made the change.
+	pub actor: String,
+	/// The block containing the payment that justified the change, if any.
+	pub payment_block_number: Option<BlockNumber>,
+	/// The expiry before the change. `None` if the para wasn't registered.
+	pub old_expiry: Option<Timestamp>,
+	/// The expiry after the change. `None` if the para got removed.
+	pub new_expiry: Option<Timestamp>,
+	/// The time at which the change was made.
+	pub timestamp: Timestamp,
+}
+
+/// Returns the audit entries describing the difference between the `old` and `new` registry.
+pub fn audit_entries(
+	old: &[Parachain],
+	new: &[Parachain],
+	origin: &ChangeOrigin,
+) -> Vec<AuditEntry> {
+	let find = |paras: &[Parachain], para: &Parachain| {
+		paras
+			.iter()
+			.find(|p| p.relay_chain == para.relay_chain && p.para_id == para.para_id)
+			.cloned()
+	};
+
+	let entry =
+		|para: &Parachain, action, old: Option<&Parachain>, new: Option<&Parachain>| AuditEntry {
+			para: (para.relay_chain.clone(), para.para_id),
+			action,
+			actor: origin.actor.clone(),
+			payment_block_number: origin.payment_block_number,
+			old_expiry: old.map(|para| para.expiry_timestamp),
+			new_expiry: new.map(|para| para.expiry_timestamp),
+			timestamp: current_timestamp(),
+		};
+
+	let mut entries = vec![];
+	for para in new {
+		let Some(previous) = find(old, para) else {
+			entries.push(entry(para, RegistryAction::Registration, None, Some(para)));
+			continue;
+		};
+
+		if previous.expiry_timestamp != para.expiry_timestamp {
+			let action = if para.expiry_timestamp > previous.expiry_timestamp {
+				RegistryAction::SubscriptionExtension
+			} else {
+				RegistryAction::SubscriptionReduction
+			};
+			entries.push(entry(para, action, Some(&previous), Some(para)));
+		}
+
+		if previous.rpcs != para.rpcs || previous.name != para.name {
+			entries.push(entry(para, RegistryAction::RpcChange, Some(&previous), Some(para)));
+		}
+	}
+
+	for para in old.iter().filter(|para| find(new, para).is_none()) {
+		entries.push(entry(para, RegistryAction::Removal, Some(para), None));
+	}
+
+	entries
+}
+
+/// Appends the entries to the audit log.
+pub fn record(entries: &[AuditEntry]) -> Result<(), std::io::Error> {
+	if entries.is_empty() {
+		return Ok(());
+	}
+
+	let mut content = vec![];
+	for entry in entries {
+		serde_json::to_writer(&mut content, entry)?;
+		content.push(b'\n');
+	}
+
+	let mut file = OpenOptions::new().create(true).append(true).open(config().audit_log)?;
+	file.write_all(&content)?;
+	file.sync_data()
+}
+
+/// Returns all the audit entries of a parachain, oldest first.
+pub fn para_history(
+	relay_chain: RelayChain,
+	para_id: ParaId,
+) -> Result<Vec<AuditEntry>, std::io::Error> {
+	let content = match std::fs::read_to_string(config().audit_log) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => return Err(e),
+	};
+
+	let entries = content
+		.lines()
+		.filter_map(|line| match serde_json::from_str::<AuditEntry>(line) {
+			Ok(entry) => Some(entry),
+			Err(e) => {
+				log::warn!(target: LOG_TARGET, "Skipping malformed audit entry: {:?}", e);
+				None
+			},
+		})
+		.filter(|entry| entry.para == (relay_chain.clone(), para_id))
+		.collect();
+
+	Ok(entries)
+}
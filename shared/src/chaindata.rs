@@ -40,15 +40,6 @@ pub enum ChainDataError {
 	ParaNotFound,
 }
 
-impl From<String> for ChainDataError {
-	fn from(v: String) -> Self {
-		match v.as_str() {
-			"ParaNotFound" => Self::ParaNotFound,
-			_ => panic!("UnknownError"),
-		}
-	}
-}
-
 /// Get the rpcs of a parachain.
 pub fn get_para(relay: RelayChain, para_id: ParaId) -> Result<Parachain, ChainDataError> {
 	let mut file = File::open(config().chaindata).expect("ChainData not found");
@@ -72,6 +63,7 @@ pub fn get_para(relay: RelayChain, para_id: ParaId) -> Result<Parachain, ChainDa
 		name: para_chaindata.name.clone(),
 		rpcs,
 		expiry_timestamp: Default::default(),
+		plan: None,
 	};
 
 	Ok(para)
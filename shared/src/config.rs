@@ -13,11 +13,43 @@
 // You should have received a copy of the GNU General Public License
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
 use subxt::utils::AccountId32;
-use types::Timestamp;
+use types::{Parachain, RelayChain, Timestamp};
 
+/// The config file which is used unless the `CONFIG_FILE` environment variable is set.
 const CONFIG_FILE: &str = "config.toml";
 
+/// Identifies an asset of the `Assets` pallet.
+pub type AssetId = u32;
+
+/// The currency in which a payment is made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Currency {
+	/// The native currency of the payment chain.
+	Native,
+	/// An asset of the `Assets` pallet, e.g. USDT on Asset Hub.
+	Asset(AssetId),
+}
+
+/// A transfer emitted by a payment extrinsic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transfer {
+	pub currency: Currency,
+	/// The account receiving the transfer.
+	pub to: AccountId32,
+	pub amount: u128,
+}
+
+/// The cost of a payment in an asset.
+#[derive(serde::Deserialize, Clone)]
+pub struct AssetCost {
+	pub asset_id: AssetId,
+	/// The cost in the smallest unit of the asset.
+	pub cost: String,
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct PaymentInfo {
 	/// The rpc url from the chain where the payment is required to occur.
@@ -33,6 +65,151 @@ pub struct PaymentInfo {
 
 	/// Defines how much before the expiry can the subscription be renewed.
 	pub renewal_period: Timestamp,
+	/// The plans which can be bought in addition to single subscription periods.
+	#[serde(default)]
+	pub plans: Vec<PaymentPlan>,
+	/// The assets which are accepted as payment in addition to the native currency, along with
+	/// the cost of a single subscription period in each of them.
+	#[serde(default)]
+	pub assets: Vec<AssetCost>,
+}
+
+/// A subscription plan, e.g. a discounted yearly subscription.
+#[derive(serde::Deserialize, Clone)]
+pub struct PaymentPlan {
+	/// The name identifying the plan.
+	pub name: String,
+	/// The cost of the plan.
+	pub cost: String,
+	/// The cost of the plan in the accepted assets.
+	///
+	/// The plan can't be bought with an asset which has no cost specified.
+	#[serde(default)]
+	pub asset_costs: Vec<AssetCost>,
+	/// The duration that a payment for the plan covers.
+	pub duration: Timestamp,
+	/// Overrides the retention period of the per-block consumption data for the parachains on
+	/// this plan.
+	pub retention_period: Option<Timestamp>,
+}
+
+/// The subscription bought with a payment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subscription {
+	/// The bought plan, or `None` if the payment bought single subscription periods.
+	pub plan: Option<String>,
+	/// The duration that the payment covers.
+	pub duration: Timestamp,
+}
+
+impl Subscription {
+	/// Extends the subscription of `para` by the bought duration and switches it to the bought
+	/// plan.
+	///
+	/// An expired subscription is extended from `now` on, so the time it was expired for isn't
+	/// paid for.
+	pub fn extend(&self, para: &mut Parachain, now: Timestamp) {
+		para.expiry_timestamp = para.expiry_timestamp.max(now).saturating_add(self.duration);
+		para.plan = self.plan.clone();
+	}
+}
+
+impl PaymentInfo {
+	/// Returns the subscription bought by paying `amount` of `currency`.
+	///
+	/// A payment matching the cost of a plan buys the plan, otherwise every multiple of the cost
+	/// buys a single `subscription_duration`. Returns `None` if `amount` doesn't cover a single
+	/// period or the currency isn't accepted.
+	pub fn subscription(&self, currency: Currency, amount: u128) -> Option<Subscription> {
+		if let Some(plan) = self
+			.plans
+			.iter()
+			.find(|plan| cost_in(&plan.cost, &plan.asset_costs, currency) == Some(amount))
+		{
+			return Some(Subscription { plan: Some(plan.name.clone()), duration: plan.duration });
+		}
+
+		let periods = amount.checked_div(cost_in(&self.cost, &self.assets, currency)?)?;
+		if periods == 0 {
+			return None;
+		}
+
+		let periods = Timestamp::try_from(periods).unwrap_or(Timestamp::MAX);
+		Some(Subscription {
+			plan: None,
+			duration: self.subscription_duration.saturating_mul(periods),
+		})
+	}
+
+	/// Returns the currency whose transfers to the `receiver` buy a subscription, along with the
+	/// amount transferred in it.
+	pub fn paid(&self, transfers: &[Transfer]) -> Option<(Currency, u128)> {
+		self.currencies().into_iter().find_map(|currency| {
+			let amount = transfers
+				.iter()
+				.filter(|transfer| transfer.currency == currency && transfer.to == self.receiver)
+				.fold(0, |paid: u128, transfer| paid.saturating_add(transfer.amount));
+
+			self.subscription(currency, amount).map(|_| (currency, amount))
+		})
+	}
+
+	/// A single subscription period, as granted when payments aren't required.
+	pub fn single_period(&self) -> Subscription {
+		Subscription { plan: None, duration: self.subscription_duration }
+	}
+
+	/// Returns the plan with the given name.
+	pub fn plan(&self, name: &str) -> Option<&PaymentPlan> {
+		self.plans.iter().find(|plan| plan.name == name)
+	}
+
+	/// Returns all the currencies accepted as payment.
+	pub fn currencies(&self) -> Vec<Currency> {
+		std::iter::once(Currency::Native)
+			.chain(self.assets.iter().map(|asset| Currency::Asset(asset.asset_id)))
+			.collect()
+	}
+}
+
+fn cost_in(native_cost: &str, asset_costs: &[AssetCost], currency: Currency) -> Option<u128> {
+	let cost = match currency {
+		Currency::Native => native_cost,
+		Currency::Asset(id) => &asset_costs.iter().find(|asset| asset.asset_id == id)?.cost,
+	};
+
+	cost.parse().ok()
+}
+
+/// The format of the webhook payloads.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+	/// The notification itself, encoded as JSON.
+	#[default]
+	Json,
+	/// A Slack compatible message.
+	Slack,
+	/// A Matrix `m.room.message` event content.
+	Matrix,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct NotifierConfig {
+	/// The url to which the notifications are posted.
+	pub webhook_url: String,
+	#[serde(default)]
+	pub format: WebhookFormat,
+	/// How often the registry is scanned, in seconds.
+	pub interval: Timestamp,
+	/// Defines how long after the expiry the grace period of a subscription ends.
+	pub grace_period: Timestamp,
+	/// Path to the record of the sent notifications.
+	pub sent_log: String,
+	/// How long to wait for connecting to the webhook and for its response, in seconds.
+	///
+	/// Defaults to 10 seconds.
+	pub webhook_timeout: Option<Timestamp>,
 }
 
 #[derive(serde::Deserialize)]
@@ -41,16 +218,50 @@ pub struct Config {
 	pub output_directory: String,
 	/// Path to the registry file.
 	pub registry: String,
+	/// Path to the audit log of the registry changes.
+	pub audit_log: String,
+	/// Path to the ledger of the used payments.
+	pub payment_ledger: String,
+	/// Path to the last blocks processed by the payment watcher.
+	pub watcher_state: String,
 	/// Path to the chaindata file.
 	pub chaindata: String,
-	/// The payment configuration.
-	pub payment_info: Option<PaymentInfo>,
+	/// The payment configuration of each relay chain.
+	///
+	/// The parachains of a relay chain without a payment configuration can be registered for free.
+	#[serde(default)]
+	pub payment_info: HashMap<RelayChain, PaymentInfo>,
+	/// Allows registering parachains without a payment even if `payment_info` is set.
+	///
+	/// Meant for testing purposes.
+	#[serde(default)]
+	pub free_mode: bool,
 	/// The Number of distinct output directories.
 	pub outputs: usize,
+	/// The bearer token required by the admin routes.
+	///
+	/// If not set, the admin routes are disabled.
+	pub admin_token: Option<String>,
+	/// The configuration of the expiry reminders.
+	///
+	/// If not set, no reminders are sent.
+	pub notifier: Option<NotifierConfig>,
+	/// Defines how long the per-block consumption data is kept once it has been rolled up.
+	///
+	/// Defined in seconds. If not set, the per-block data is kept indefinitely.
+	pub retention_period: Option<Timestamp>,
+}
+
+impl Config {
+	/// Returns the payment configuration of the relay chain.
+	pub fn payment_info_for(&self, relay_chain: &RelayChain) -> Option<&PaymentInfo> {
+		self.payment_info.get(relay_chain)
+	}
 }
 
 pub fn config() -> Config {
-	let config_str = std::fs::read_to_string(CONFIG_FILE).expect("Failed to read config file");
+	let path = std::env::var("CONFIG_FILE").unwrap_or_else(|_| CONFIG_FILE.to_string());
+	let config_str = std::fs::read_to_string(path).expect("Failed to read config file");
 	toml::from_str(&config_str).expect("Failed to parse config file")
 }
 
@@ -63,3 +274,9 @@ pub fn output_directory(rpc_index: Option<usize>) -> String {
 		format!("{}/out", output_dir)
 	}
 }
+
+pub fn rollup_directory() -> String {
+	let output_dir = config().output_directory.trim_end_matches('/').to_string();
+
+	format!("{}/rollups", output_dir)
+}
@@ -13,23 +13,203 @@
 // You should have received a copy of the GNU General Public License
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::{config::output_directory, LOG_TARGET};
-use csv::{ReaderBuilder, WriterBuilder};
-use std::fs::{File, OpenOptions};
-use types::{Parachain, WeightConsumption};
+use crate::{
+	archive::{archived_months, archived_rows, prune_archives},
+	config::output_directory,
+	write_atomically, LOG_TARGET,
+};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use std::{
+	collections::HashSet,
+	fs::{File, OpenOptions},
+	io::{BufRead, BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+};
+use types::{Parachain, Timestamp, WeightConsumption};
 
+/// The schema version with which the consumption files are written.
+///
+/// Files of version 1 don't have a header. Starting from version 2 each file begins with a line
+/// containing the schema version, followed by a header row with the names of the columns.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The names of the columns of the current schema version.
+pub const COLUMNS: [&str; 8] = [
+	"block_number",
+	"timestamp",
+	"normal_dispatch_ref_time",
+	"operational_dispatch_ref_time",
+	"mandatory_dispatch_ref_time",
+	"normal_proof_size",
+	"operational_proof_size",
+	"mandatory_proof_size",
+];
+
+const SCHEMA_VERSION_PREFIX: &str = "#schema_version=";
+
+/// The consumption data read from a consumption file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StoredConsumption {
+	/// The consumption data of all the rows that could be parsed.
+	pub entries: Vec<WeightConsumption>,
+	/// The number of rows that were skipped since they couldn't be parsed.
+	pub skipped: usize,
+}
+
+/// The result of checking the integrity of a consumption file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IntegrityReport {
+	/// The total number of rows in the file.
+	pub rows: usize,
+	/// The lines containing rows that couldn't be parsed.
+	pub malformed: Vec<u64>,
+	/// The lines containing a block number lower than the one of a preceding row.
+	pub out_of_order: Vec<u64>,
+	/// The lines containing a block which was already recorded by a preceding row.
+	pub duplicates: Vec<u64>,
+	/// Whether the last row is missing its line terminator, meaning that the write was
+	/// interrupted.
+	pub torn: bool,
+}
+
+impl IntegrityReport {
+	pub fn is_ok(&self) -> bool {
+		self.malformed.is_empty() &&
+			self.out_of_order.is_empty() &&
+			self.duplicates.is_empty() &&
+			!self.torn
+	}
+}
+
+/// Returns all the stored consumption of a parachain.
+///
+/// For the processed output this also includes the consumption of the archived months.
 pub fn get_consumption(
 	para: Parachain,
 	rpc_index: Option<usize>,
-) -> Result<Vec<WeightConsumption>, &'static str> {
-	let file =
-		File::open(output_file_path(para, rpc_index)).map_err(|_| "Consumption data not found")?;
-	let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(file);
-
-	let consumption: Vec<WeightConsumption> = rdr
-		.deserialize::<WeightConsumption>()
-		.filter_map(|result| result.ok())
-		.collect();
+) -> Result<StoredConsumption, &'static str> {
+	get_consumption_between(para, rpc_index, 0, Timestamp::MAX)
+}
+
+/// Returns the stored consumption of the blocks with a timestamp between `start` and `end`, both
+/// inclusive.
+///
+/// For the processed output only the archives of the months overlapping with the range are read.
+pub fn get_consumption_between(
+	para: Parachain,
+	rpc_index: Option<usize>,
+	start: Timestamp,
+	end: Timestamp,
+) -> Result<StoredConsumption, &'static str> {
+	let mut reader = read_consumption_between(para, rpc_index, start, end)?;
+	let entries = reader.by_ref().collect();
+
+	Ok(StoredConsumption { entries, skipped: reader.skipped })
+}
+
+/// Lazily reads the stored consumption of the blocks with a timestamp between `start` and `end`,
+/// both inclusive.
+///
+/// The files are opened upfront, while their rows are only read once the returned iterator gets
+/// to them. The consumption is yielded in the order in which it is stored, i.e. the archived
+/// months in chronological order followed by the live file.
+pub fn read_consumption_between(
+	para: Parachain,
+	rpc_index: Option<usize>,
+	start: Timestamp,
+	end: Timestamp,
+) -> Result<ConsumptionReader, &'static str> {
+	let archived: Box<dyn Iterator<Item = Row> + Send> = match rpc_index {
+		None => Box::new(
+			archived_rows(para.clone(), start, end)
+				.map_err(|_| "Failed to read archived consumption data")?,
+		),
+		Some(_) => Box::new(std::iter::empty()),
+	};
+
+	let live: Box<dyn Iterator<Item = Row> + Send> =
+		match File::open(output_file_path(para.clone(), rpc_index)) {
+			Ok(file) => Box::new(rows(file).map_err(|_| "Unsupported consumption data")?),
+			// All the consumption data might have been archived.
+			Err(e)
+				if e.kind() == ErrorKind::NotFound &&
+					rpc_index.is_none() &&
+					!archived_months(para.clone()).is_empty() =>
+				Box::new(std::iter::empty()),
+			Err(e) if e.kind() == ErrorKind::NotFound => return Err("Consumption data not found"),
+			Err(_) => return Err("Unsupported consumption data"),
+		};
+
+	Ok(ConsumptionReader {
+		rows: Box::new(
+			archived.map(|(_, row)| (true, row)).chain(live.map(|(_, row)| (false, row))),
+		),
+		para,
+		start,
+		end,
+		last_archived: None,
+		skipped: 0,
+	})
+}
+
+/// Lazily reads the stored consumption, see [`read_consumption_between`].
+pub struct ConsumptionReader {
+	/// The rows along with whether they are archived.
+	rows: Box<dyn Iterator<Item = (bool, Option<WeightConsumption>)> + Send>,
+	para: Parachain,
+	start: Timestamp,
+	end: Timestamp,
+	/// The highest archived block read so far.
+	last_archived: Option<u32>,
+	/// The number of rows read so far that were skipped since they couldn't be parsed.
+	pub skipped: usize,
+}
+
+impl Iterator for ConsumptionReader {
+	type Item = WeightConsumption;
+
+	fn next(&mut self) -> Option<WeightConsumption> {
+		loop {
+			let (archived, row) = self.rows.next()?;
+
+			let Some(entry) = row else {
+				self.skipped += 1;
+				continue;
+			};
+
+			if archived {
+				self.last_archived = self.last_archived.max(Some(entry.block_number));
+			} else if self.last_archived.is_some_and(|last| entry.block_number <= last) {
+				// If a rotation got interrupted the same block can be both archived and in the
+				// live file.
+				continue;
+			}
+
+			if entry.timestamp >= self.start && entry.timestamp <= self.end {
+				return Some(entry);
+			}
+		}
+	}
+}
+
+impl Drop for ConsumptionReader {
+	fn drop(&mut self) {
+		log_skipped(&self.para, self.skipped);
+	}
+}
+
+/// Returns the consumption stored in the live file, i.e. the consumption which hasn't been
+/// archived.
+pub fn get_live_consumption(
+	para: Parachain,
+	rpc_index: Option<usize>,
+) -> Result<StoredConsumption, &'static str> {
+	let consumption =
+		read_file(&output_file_path(para.clone(), rpc_index)).map_err(|e| match e.kind() {
+			ErrorKind::NotFound => "Consumption data not found",
+			_ => "Unsupported consumption data",
+		})?;
+
+	log_skipped(&para, consumption.skipped);
 
 	Ok(consumption)
 }
@@ -45,15 +225,8 @@ pub fn write_consumption(
 		para.relay_chain, para.para_id, consumption.block_number
 	);
 
-	let output_file_path = output_file_path(para, rpc_index);
-	let file = OpenOptions::new().create(true).append(true).open(output_file_path)?;
-
-	let mut wtr = WriterBuilder::new().from_writer(file);
-
 	// The data is stored in the sequence described at the beginning of the file.
-	wtr.write_record(&consumption.to_csv())?;
-
-	wtr.flush()
+	append_records(&output_file_path(para, rpc_index), vec![consumption.to_csv()])
 }
 
 pub fn write_batch_consumption(
@@ -66,18 +239,125 @@ pub fn write_batch_consumption(
 		para.relay_chain, para.para_id
 	);
 
-	let output_file_path = output_file_path(para, None);
-	let file = OpenOptions::new().create(true).append(true).open(output_file_path)?;
+	// The data is stored in the sequence described at the beginning of the file.
+	append_records(
+		&output_file_path(para, None),
+		consumption.iter().map(|entry| entry.to_csv()).collect(),
+	)
+}
+
+/// Removes the consumption data of all the blocks that were produced before `cutoff`, including
+/// the archived data.
+pub fn prune_consumption(para: Parachain, cutoff: Timestamp) -> Result<(), std::io::Error> {
+	log::info!(
+		target: LOG_TARGET,
+		"{}-{} - Pruning weight consumption older than: {}",
+		para.relay_chain, para.para_id, cutoff
+	);
+
+	let path = output_file_path(para.clone(), None);
+	let consumption: Vec<WeightConsumption> = read_file(&path)?
+		.entries
+		.into_iter()
+		.filter(|entry| entry.timestamp >= cutoff)
+		.collect();
+
+	write_atomically(&path, &consumption_content(&consumption)?)?;
+
+	prune_archives(para, cutoff)
+}
+
+/// Checks the consumption file for malformed rows, non-monotonic block numbers, duplicate blocks
+/// and an interrupted last write.
+pub fn verify_consumption(
+	para: Parachain,
+	rpc_index: Option<usize>,
+) -> Result<IntegrityReport, std::io::Error> {
+	let path = output_file_path(para, rpc_index);
+	let rows = read_rows(File::open(&path)?)?;
 
-	let mut wtr = WriterBuilder::new().from_writer(file);
+	let mut report = IntegrityReport {
+		rows: rows.len(),
+		torn: is_torn(&mut File::open(&path)?)?,
+		..Default::default()
+	};
 
-	// TODO: add a to_csv function
-	consumption.iter().try_for_each(|entry| {
-		// The data is stored in the sequence described at the beginning of the file.
-		wtr.write_record(&entry.to_csv())
-	})?;
+	let mut seen = HashSet::new();
+	let mut highest = None;
+	rows.iter().for_each(|(line, row)| match row {
+		None => report.malformed.push(*line),
+		Some(entry) if !seen.insert(entry.block_number) => report.duplicates.push(*line),
+		Some(entry) => {
+			if highest.is_some_and(|highest| entry.block_number < highest) {
+				report.out_of_order.push(*line);
+			}
+			highest = highest.max(Some(entry.block_number));
+		},
+	});
 
-	wtr.flush()
+	Ok(report)
+}
+
+/// Fixes all the issues found by `verify_consumption`.
+///
+/// Malformed rows and an interrupted last row are dropped, for duplicate blocks the first row is
+/// kept and the remaining rows are sorted by block number. Returns the report of the issues found
+/// before the repair.
+pub fn repair_consumption(
+	para: Parachain,
+	rpc_index: Option<usize>,
+) -> Result<IntegrityReport, std::io::Error> {
+	let report = verify_consumption(para.clone(), rpc_index)?;
+	if report.is_ok() {
+		return Ok(report);
+	}
+
+	log::info!(
+		target: LOG_TARGET,
+		"{}-{} - Repairing weight consumption.",
+		para.relay_chain, para.para_id
+	);
+
+	let path = output_file_path(para, rpc_index);
+	let mut rows = read_rows(File::open(&path)?)?;
+	if report.torn {
+		rows.pop();
+	}
+
+	let mut seen = HashSet::new();
+	let mut entries: Vec<WeightConsumption> = rows
+		.into_iter()
+		.filter_map(|(_, row)| row)
+		.filter(|entry| seen.insert(entry.block_number))
+		.collect();
+	entries.sort_by_key(|entry| entry.block_number);
+
+	write_atomically(&path, &consumption_content(&entries)?)?;
+
+	Ok(report)
+}
+
+/// Rewrites the consumption file using the current schema version.
+///
+/// Returns the schema version the file had before the migration.
+pub fn migrate_consumption(
+	para: Parachain,
+	rpc_index: Option<usize>,
+) -> Result<u32, std::io::Error> {
+	let path = output_file_path(para.clone(), rpc_index);
+	let version = schema_version(&mut File::open(&path)?)?;
+
+	if version != SCHEMA_VERSION {
+		log::info!(
+			target: LOG_TARGET,
+			"{}-{} - Migrating weight consumption from schema version {} to {}.",
+			para.relay_chain, para.para_id, version, SCHEMA_VERSION
+		);
+
+		migrate_file(&path)?;
+	}
+
+	Ok(version)
 }
 
 pub fn delete_consumption(para: Parachain, rpc_index: usize) {
@@ -106,6 +386,214 @@ pub fn delete_consumption(para: Parachain, rpc_index: usize) {
 	}
 }
 
-fn output_file_path(para: Parachain, rpc_index: Option<usize>) -> String {
+pub(crate) fn output_file_path(para: Parachain, rpc_index: Option<usize>) -> String {
 	format!("{}/{}-{}.csv", output_directory(rpc_index), para.relay_chain, para.para_id)
 }
+
+/// A row of a consumption file along with its line number.
+///
+/// Rows which couldn't be parsed are `None`.
+pub(crate) type Row = (u64, Option<WeightConsumption>);
+
+/// Reads all the rows of a consumption file.
+fn read_rows<R: Read + Send + 'static>(reader: R) -> Result<Vec<Row>, std::io::Error> {
+	Ok(rows(reader)?.collect())
+}
+
+/// Lazily reads the rows of a consumption file.
+///
+/// Only the schema version is read upfront, the rows are read as the returned iterator advances.
+pub(crate) fn rows<R: Read + Send + 'static>(
+	reader: R,
+) -> Result<impl Iterator<Item = Row> + Send, std::io::Error> {
+	let mut reader = BufReader::new(reader);
+	let mut first_line = vec![];
+	reader.read_until(b'\n', &mut first_line)?;
+
+	let version = parse_schema_version(&first_line)?;
+	if version == 0 || version > SCHEMA_VERSION {
+		return Err(std::io::Error::new(
+			ErrorKind::InvalidData,
+			format!("Unsupported schema version: {}", version),
+		));
+	}
+
+	let rdr = ReaderBuilder::new()
+		// Skips the schema version line.
+		.comment(Some(b'#'))
+		.has_headers(version > 1)
+		// The first line was already consumed from the reader.
+		.from_reader(Cursor::new(first_line).chain(reader));
+
+	Ok(rdr.into_records().map(move |record| match record {
+		Ok(record) => (line(&record), parse_row(version, &record)),
+		Err(e) => (e.position().map(|p| p.line()).unwrap_or_default(), None),
+	}))
+}
+
+/// Parses a row stored with the given schema version.
+fn parse_row(version: u32, record: &StringRecord) -> Option<WeightConsumption> {
+	match version {
+		// Version 2 only introduced the header, the columns are the same.
+		1 | 2 => record.deserialize(None).ok(),
+		_ => None,
+	}
+}
+
+/// Returns the schema version of a consumption file.
+///
+/// Files which don't start with a schema version line were written before the schema got
+/// versioned, so they are of version 1.
+fn schema_version<R: Read + Seek>(reader: &mut R) -> Result<u32, std::io::Error> {
+	let mut first_line = vec![];
+	reader.seek(SeekFrom::Start(0))?;
+	BufReader::new(&mut *reader).read_until(b'\n', &mut first_line)?;
+	reader.seek(SeekFrom::Start(0))?;
+
+	parse_schema_version(&first_line)
+}
+
+/// Parses the schema version from the first line of a consumption file.
+fn parse_schema_version(first_line: &[u8]) -> Result<u32, std::io::Error> {
+	if first_line.is_empty() {
+		return Ok(SCHEMA_VERSION);
+	}
+
+	match String::from_utf8_lossy(first_line)
+		.trim_end()
+		.strip_prefix(SCHEMA_VERSION_PREFIX)
+	{
+		Some(version) => version
+			.parse()
+			.map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Invalid schema version")),
+		None => Ok(1),
+	}
+}
+
+/// Rewrites the file using the current schema version.
+///
+/// Rows which couldn't be parsed are dropped, just like they are ignored by all the readers.
+fn migrate_file(path: &str) -> Result<(), std::io::Error> {
+	let rows = read_rows(File::open(path)?)?;
+
+	let skipped = rows.iter().filter(|(_, row)| row.is_none()).count();
+	if skipped > 0 {
+		log::warn!(
+			target: LOG_TARGET,
+			"{} - Dropping {} malformed rows during the migration",
+			path, skipped
+		);
+	}
+
+	let entries: Vec<WeightConsumption> = rows.into_iter().filter_map(|(_, row)| row).collect();
+	write_atomically(path, &consumption_content(&entries)?)
+}
+
+/// Reads a consumption file.
+fn read_file(path: &str) -> Result<StoredConsumption, std::io::Error> {
+	Ok(stored(read_rows(File::open(path)?)?))
+}
+
+pub(crate) fn stored(rows: Vec<Row>) -> StoredConsumption {
+	StoredConsumption {
+		skipped: rows.iter().filter(|(_, row)| row.is_none()).count(),
+		entries: rows.into_iter().filter_map(|(_, row)| row).collect(),
+	}
+}
+
+fn log_skipped(para: &Parachain, skipped: usize) {
+	if skipped > 0 {
+		log::warn!(
+			target: LOG_TARGET,
+			"{}-{} - Skipped {} malformed consumption rows",
+			para.relay_chain, para.para_id, skipped
+		);
+	}
+}
+
+/// Returns the content of a consumption file containing the given entries.
+pub(crate) fn consumption_content(
+	entries: &[WeightConsumption],
+) -> Result<Vec<u8>, std::io::Error> {
+	let mut content = header()?;
+	content.extend(csv_content(entries.iter().map(|entry| entry.to_csv()).collect())?);
+
+	Ok(content)
+}
+
+/// The header with which each consumption file starts.
+fn header() -> Result<Vec<u8>, std::io::Error> {
+	let mut header = format!("{}{}\n", SCHEMA_VERSION_PREFIX, SCHEMA_VERSION).into_bytes();
+	header.extend(csv_content(vec![COLUMNS.iter().map(|column| column.to_string()).collect()])?);
+
+	Ok(header)
+}
+
+fn line(record: &StringRecord) -> u64 {
+	record.position().map(|p| p.line()).unwrap_or_default()
+}
+
+/// Appends the records to the file in a single write.
+///
+/// If the previous write was interrupted the incomplete row is removed first, so that it doesn't
+/// get merged with the new data. Files written with an older schema version get migrated first.
+fn append_records(path: &str, records: Vec<Vec<String>>) -> Result<(), std::io::Error> {
+	let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+
+	if is_torn(&mut file)? {
+		log::warn!(
+			target: LOG_TARGET,
+			"{} - Removing an incomplete row left by an interrupted write",
+			path
+		);
+		let len = last_line_end(&mut file)?;
+		file.set_len(len)?;
+	}
+
+	if file.metadata()?.len() > 0 && schema_version(&mut file)? != SCHEMA_VERSION {
+		migrate_file(path)?;
+		// The migrated file replaced the one we opened.
+		file = OpenOptions::new().read(true).append(true).open(path)?;
+	}
+
+	let mut content = if file.metadata()?.len() == 0 { header()? } else { vec![] };
+	content.extend(csv_content(records)?);
+
+	file.write_all(&content)?;
+	file.sync_data()
+}
+
+/// Encodes the records as CSV, each followed by a line terminator.
+pub fn csv_content(records: Vec<Vec<String>>) -> Result<Vec<u8>, std::io::Error> {
+	let mut wtr = WriterBuilder::new().from_writer(vec![]);
+	records.iter().try_for_each(|record| wtr.write_record(record))?;
+
+	wtr.into_inner().map_err(|e| e.into_error())
+}
+
+/// Returns whether the file is not empty and doesn't end with a line terminator.
+fn is_torn(file: &mut File) -> Result<bool, std::io::Error> {
+	let len = file.metadata()?.len();
+	Ok(len > 0 && last_line_end(file)? != len)
+}
+
+/// Returns the position right after the last line terminator of the file.
+fn last_line_end(file: &mut File) -> Result<u64, std::io::Error> {
+	let mut buf = [0u8; 1024];
+	let mut end = file.metadata()?.len();
+
+	while end > 0 {
+		let start = end.saturating_sub(buf.len() as u64);
+		let chunk = &mut buf[..(end - start) as usize];
+
+		file.seek(SeekFrom::Start(start))?;
+		file.read_exact(chunk)?;
+
+		if let Some(position) = chunk.iter().rposition(|byte| *byte == b'\n') {
+			return Ok(start + position as u64 + 1);
+		}
+		end = start;
+	}
+
+	Ok(0)
+}
@@ -0,0 +1,85 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The parts of a payment extrinsic which the payment verification is based on.
+//!
+//! These are decoded from the payment chain by the `payment` module, while the verification itself
+//! doesn't depend on the chain client.
+
+use crate::{
+	config::{Currency, PaymentInfo, Transfer},
+	payment::PaymentError,
+	watcher::payment_remark,
+};
+use types::{ParaId, RelayChain};
+
+/// The call of an extrinsic, decoded as far as needed to find the remarks it makes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Call {
+	/// `System::remark` or `System::remark_with_event` making the given remark.
+	Remark(Vec<u8>),
+	/// Any other call, along with the calls it dispatches, e.g. the calls of a
+	/// `Utility::batch_all` or the call of a `Proxy::proxy` or `Multisig::as_multi`.
+	Other(Vec<Call>),
+}
+
+impl Call {
+	/// Returns the remarks made by the call and the calls it dispatches.
+	pub fn remarks(&self) -> Vec<&[u8]> {
+		match self {
+			Call::Remark(remark) => vec![remark.as_slice()],
+			Call::Other(calls) => calls.iter().flat_map(Call::remarks).collect(),
+		}
+	}
+}
+
+/// What an extrinsic of the payment chain did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtrinsicSummary {
+	/// The decoded call of the extrinsic.
+	pub call: Call,
+	/// The transfers emitted by the extrinsic, in any of the currencies.
+	pub transfers: Vec<Transfer>,
+	/// Whether the extrinsic emitted `System::ExtrinsicSuccess`.
+	pub succeeded: bool,
+}
+
+impl ExtrinsicSummary {
+	/// Ensures that the extrinsic paid for the parachain.
+	///
+	/// The transfers are taken from the events, so the payment can be made in any way, e.g. with
+	/// `transfer_allow_death` or through a proxy or a multisig. The extrinsic has to transfer
+	/// enough of one of the accepted currencies to the `receiver` to buy a subscription, and one of
+	/// its calls has to make exactly the remark of the parachain. The remark is matched against
+	/// the decoded calls rather than the encoded extrinsic, so its bytes appearing within another
+	/// argument don't count.
+	///
+	/// Returns the currency and the amount paid.
+	pub fn payment_for(
+		&self,
+		para: &(RelayChain, ParaId),
+		payment_info: &PaymentInfo,
+	) -> Result<(Currency, u128), PaymentError> {
+		if !self.succeeded {
+			return Err(PaymentError::Failed);
+		}
+
+		let remark = payment_remark(para);
+		match payment_info.paid(&self.transfers) {
+			Some(paid) if self.call.remarks().contains(&remark.as_slice()) => Ok(paid),
+			_ => Err(PaymentError::NotFound),
+		}
+	}
+}
@@ -0,0 +1,167 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Ledger of the payments which were already used, preventing the same payment from being used
+//! more than once.
+//!
+//! Each line of the ledger is a JSON encoded [`LedgerEntry`].
+
+use crate::{
+	config::{config, Currency},
+	current_timestamp,
+	payment::PaymentError,
+	write_atomically, LOG_TARGET,
+};
+use polkadot_core_primitives::BlockNumber;
+use serde::{Deserialize, Serialize};
+use std::{
+	fs::OpenOptions,
+	io::{ErrorKind, Write},
+};
+use types::{ParaId, RelayChain, Timestamp};
+
+/// References the payment extrinsic provided by the caller.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentReference {
+	/// The extrinsic at the given index within the block.
+	Index { block_number: BlockNumber, index: u32 },
+	/// The extrinsic with the given hash within the block.
+	Hash { block_number: BlockNumber, hash: String },
+}
+
+impl PaymentReference {
+	/// The block in which the payment occurred.
+	pub fn block_number(&self) -> BlockNumber {
+		match self {
+			Self::Index { block_number, .. } | Self::Hash { block_number, .. } => *block_number,
+		}
+	}
+}
+
+/// Uniquely identifies a payment extrinsic.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PaymentId {
+	/// The genesis hash of the chain on which the payment occurred.
+	pub chain: String,
+	/// The hash of the block containing the payment.
+	pub block_hash: String,
+	/// The index of the payment extrinsic within the block.
+	pub extrinsic_index: u32,
+}
+
+/// Describes a validated payment.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentReceipt {
+	pub payment: PaymentId,
+	/// The block in which the payment occurred.
+	pub block_number: BlockNumber,
+	/// The hash of the payment extrinsic.
+	pub extrinsic_hash: String,
+	/// The amount paid to the receiver.
+	pub amount: u128,
+	/// The currency in which the payment was made.
+	pub currency: Currency,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+	pub receipt: PaymentReceipt,
+	/// The parachain for which the payment was used.
+	pub para: (RelayChain, ParaId),
+	/// The time at which the payment was used.
+	pub timestamp: Timestamp,
+}
+
+/// Returns whether the payment was already used.
+pub fn is_used(payment: &PaymentId) -> Result<bool, PaymentError> {
+	Ok(ledger_entries()?.iter().any(|entry| &entry.receipt.payment == payment))
+}
+
+/// Records the payment as used for the given parachain.
+///
+/// This must be called while holding the registry lock, i.e. through
+/// [`crate::registry::modify_registry_with_payment`], so that a payment can't be used by two
+/// concurrent requests.
+pub fn consume_payment(
+	receipt: PaymentReceipt,
+	para: (RelayChain, ParaId),
+) -> Result<(), PaymentError> {
+	if is_used(&receipt.payment)? {
+		return Err(PaymentError::AlreadyUsed);
+	}
+
+	let entry = LedgerEntry { receipt, para, timestamp: current_timestamp() };
+
+	let mut content = serde_json::to_vec(&entry).map_err(|_| PaymentError::ValidationFailed)?;
+	content.push(b'\n');
+
+	OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(config().payment_ledger)
+		.and_then(|mut file| {
+			file.write_all(&content)?;
+			file.sync_data()
+		})
+		.map_err(|e| {
+			log::error!(target: LOG_TARGET, "Failed to record payment: {:?}", e);
+			PaymentError::ValidationFailed
+		})
+}
+
+/// Removes the payment from the ledger, so it can be used again.
+///
+/// Only used to undo [`consume_payment`] when the modification using the payment can't be
+/// completed, so it must be called while holding the registry lock as well.
+pub(crate) fn release_payment(payment: &PaymentId) -> Result<(), PaymentError> {
+	let content = match std::fs::read_to_string(config().payment_ledger) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+		Err(e) => {
+			log::error!(target: LOG_TARGET, "Failed to read the payment ledger: {:?}", e);
+			return Err(PaymentError::ValidationFailed);
+		},
+	};
+
+	let remaining: String = content
+		.lines()
+		.filter(|line| {
+			serde_json::from_str::<LedgerEntry>(line)
+				.map_or(true, |entry| &entry.receipt.payment != payment)
+		})
+		.map(|line| format!("{}\n", line))
+		.collect();
+
+	write_atomically(&config().payment_ledger, remaining.as_bytes()).map_err(|e| {
+		log::error!(target: LOG_TARGET, "Failed to write the payment ledger: {:?}", e);
+		PaymentError::ValidationFailed
+	})
+}
+
+fn ledger_entries() -> Result<Vec<LedgerEntry>, PaymentError> {
+	let content = match std::fs::read_to_string(config().payment_ledger) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => {
+			log::error!(target: LOG_TARGET, "Failed to read the payment ledger: {:?}", e);
+			return Err(PaymentError::ValidationFailed);
+		},
+	};
+
+	// Entries which fail to parse can only come from an interrupted write, in which case the
+	// payment didn't get used.
+	Ok(content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
@@ -14,25 +14,34 @@
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
+	fs::File,
+	io::Write,
 	process::Command,
 	time::{SystemTime, UNIX_EPOCH},
 };
 use types::Timestamp;
 
+pub mod archive;
+pub mod audit;
 pub mod chaindata;
 pub mod config;
 pub mod consumption;
+pub mod extrinsic;
+pub mod ledger;
+pub mod notifier;
 pub mod payment;
 pub mod registry;
+pub mod rollup;
+pub mod watcher;
 
 #[cfg(feature = "test-utils")]
-use crate::config::output_directory;
+use crate::config::{output_directory, rollup_directory};
 
 const LOG_TARGET: &str = "shared";
 
 /// Rounds a number to a fixed number of decimals.
-pub fn round_to(number: f32, decimals: i32) -> f32 {
-	let factor = 10f32.powi(decimals);
+pub fn round_to(number: f64, decimals: i32) -> f64 {
+	let factor = 10f64.powi(decimals);
 	(number * factor).round() / factor
 }
 
@@ -43,6 +52,19 @@ pub fn current_timestamp() -> Timestamp {
 	SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
 }
 
+/// Replaces the content of the file at `path`.
+///
+/// The content is first written to a temporary file which then gets renamed into place, so a
+/// reader never observes a partially written file.
+pub fn write_atomically(path: &str, content: &[u8]) -> Result<(), std::io::Error> {
+	let tmp_path = format!("{}.tmp", path);
+
+	let mut file = File::create(&tmp_path)?;
+	file.write_all(content)?;
+	file.sync_all()?;
+
+	std::fs::rename(tmp_path, path)
+}
 
 pub fn init_tracker() {
 	let output = Command::new("./scripts/init.sh").output().expect("Failed to execute command");
@@ -58,8 +80,24 @@ pub fn init_tracker() {
 // There isn't a good reason to use this other than for testing.
 #[cfg(feature = "test-utils")]
 pub fn reset_mock_environment() {
+	// Tests may replace the files by directories to make accessing them fail.
+	let remove = |path: String| {
+		let _ = std::fs::remove_file(&path).or_else(|_| std::fs::remove_dir_all(&path));
+	};
+
 	// Reset the registered paras file:
-	let _registry = registry::init_registry();
+	remove(format!("{}.tmp", config::config().registry));
+	registry::init_registry().expect("Failed to reset the registry");
+	// Clear the audit log:
+	remove(config::config().audit_log);
+	// Clear the payment ledger:
+	remove(config::config().payment_ledger);
+	// Clear the last blocks processed by the payment watcher:
+	remove(config::config().watcher_state);
+	// Clear the sent notifications:
+	if let Some(notifier) = config::config().notifier {
+		let _ = std::fs::remove_file(notifier.sent_log);
+	}
 
 	let output_path = output_directory(None);
 	// Remove the output files:
@@ -70,6 +108,11 @@ pub fn reset_mock_environment() {
 		let path = entry.path();
 		if path.is_file() {
 			std::fs::remove_file(path).expect("Failed to remove consumption data")
+		} else if path.is_dir() {
+			std::fs::remove_dir_all(path).expect("Failed to remove archived consumption data")
 		}
 	}
+
+	// Remove the rollups:
+	let _ = std::fs::remove_dir_all(rollup_directory());
 }
@@ -0,0 +1,249 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reminds about expiring subscriptions through webhooks.
+//!
+//! Each sent notification is recorded in the `sent_log` as a JSON encoded [`SentNotification`]
+//! per line, so that no notification is sent twice.
+
+use crate::{
+	config::{config, NotifierConfig, WebhookFormat},
+	current_timestamp,
+	registry::{registered_paras, RegistryError},
+	LOG_TARGET,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+	fs::OpenOptions,
+	io::{ErrorKind, Write},
+	time::Duration,
+};
+use types::{ParaId, Parachain, RelayChain, Timestamp};
+
+/// The webhook timeout used unless the config sets one, in seconds.
+const DEFAULT_WEBHOOK_TIMEOUT: Timestamp = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+	/// The subscription can be renewed.
+	RenewalWindowOpened,
+	/// The subscription expired.
+	Expired,
+	/// The grace period following the expiry of the subscription ended.
+	GracePeriodEnded,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+	pub para: (RelayChain, ParaId),
+	/// The name of the parachain.
+	pub name: String,
+	pub kind: NotificationKind,
+	/// The expiry of the subscription which the notification is about.
+	pub expiry_timestamp: Timestamp,
+}
+
+impl Notification {
+	/// Returns whether both notifications are about the same stage of the same subscription.
+	///
+	/// The name isn't compared, so renaming the parachain doesn't notify about it again.
+	pub fn is_same_as(&self, other: &Notification) -> bool {
+		self.para == other.para &&
+			self.kind == other.kind &&
+			self.expiry_timestamp == other.expiry_timestamp
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SentNotification {
+	pub notification: Notification,
+	/// The time at which the notification was sent.
+	pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum NotifierError {
+	/// Failed to read the registry.
+	Registry(RegistryError),
+	/// Failed to read the sent notifications.
+	ReadFailed,
+	/// Failed to record a sent notification.
+	WriteFailed,
+	/// The webhook didn't accept the notification.
+	WebhookFailed,
+}
+
+/// Returns the notifications which are due for the parachains at `now`.
+///
+/// Only the latest stage a subscription reached is notified about, so no outdated reminders are
+/// sent after the notifier was down for a while. Subscriptions which never expire are skipped.
+pub fn due_notifications(
+	paras: &[Parachain],
+	now: Timestamp,
+	grace_period: Timestamp,
+) -> Vec<Notification> {
+	let config = config();
+
+	paras
+		.iter()
+		.filter(|para| para.expiry_timestamp != Timestamp::MAX)
+		.filter_map(|para| {
+			let expiry = para.expiry_timestamp;
+			// Without payments there is no renewal window.
+			let renewal_period = config
+				.payment_info_for(&para.relay_chain)
+				.map(|payment_info| payment_info.renewal_period);
+
+			let kind = if now >= expiry.saturating_add(grace_period) {
+				NotificationKind::GracePeriodEnded
+			} else if now >= expiry {
+				NotificationKind::Expired
+			} else if now >= expiry.saturating_sub(renewal_period?) {
+				NotificationKind::RenewalWindowOpened
+			} else {
+				return None;
+			};
+
+			Some(Notification {
+				para: (para.relay_chain.clone(), para.para_id),
+				name: para.name.clone(),
+				kind,
+				expiry_timestamp: expiry,
+			})
+		})
+		.collect()
+}
+
+/// Sends all the due notifications which weren't sent yet.
+///
+/// Returns the sent notifications. A notification which fails to be sent is retried on the next
+/// call.
+pub fn notify(notifier: &NotifierConfig) -> Result<Vec<Notification>, NotifierError> {
+	notify_at(notifier, current_timestamp())
+}
+
+/// Sends all the notifications which are due at `now` and weren't sent yet.
+pub fn notify_at(
+	notifier: &NotifierConfig,
+	now: Timestamp,
+) -> Result<Vec<Notification>, NotifierError> {
+	let paras = registered_paras().map_err(NotifierError::Registry)?;
+	let sent = sent_notifications(notifier)?;
+
+	let mut notified = vec![];
+	for notification in due_notifications(&paras, now, notifier.grace_period) {
+		if sent.iter().any(|sent| sent.notification.is_same_as(&notification)) {
+			continue;
+		}
+
+		if let Err(e) = send(notifier, &notification) {
+			log::error!(
+				target: LOG_TARGET,
+				"{}-{} - Failed to send notification: {:?}",
+				notification.para.0,
+				notification.para.1,
+				e
+			);
+			continue;
+		}
+
+		record_sent(notifier, &notification)?;
+		notified.push(notification);
+	}
+
+	Ok(notified)
+}
+
+/// Returns all the sent notifications.
+pub fn sent_notifications(
+	notifier: &NotifierConfig,
+) -> Result<Vec<SentNotification>, NotifierError> {
+	let content = match std::fs::read_to_string(&notifier.sent_log) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => {
+			log::error!(target: LOG_TARGET, "Failed to read the sent notifications: {:?}", e);
+			return Err(NotifierError::ReadFailed);
+		},
+	};
+
+	// Entries which fail to parse can only come from an interrupted write.
+	Ok(content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
+
+fn record_sent(
+	notifier: &NotifierConfig,
+	notification: &Notification,
+) -> Result<(), NotifierError> {
+	let entry =
+		SentNotification { notification: notification.clone(), timestamp: current_timestamp() };
+
+	let mut content = serde_json::to_vec(&entry).map_err(|_| NotifierError::WriteFailed)?;
+	content.push(b'\n');
+
+	OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(&notifier.sent_log)
+		.and_then(|mut file| {
+			file.write_all(&content)?;
+			file.sync_data()
+		})
+		.map_err(|e| {
+			log::error!(target: LOG_TARGET, "Failed to record sent notification: {:?}", e);
+			NotifierError::WriteFailed
+		})
+}
+
+fn send(notifier: &NotifierConfig, notification: &Notification) -> Result<(), NotifierError> {
+	let payload = match notifier.format {
+		WebhookFormat::Json => json!(notification),
+		WebhookFormat::Slack => json!({ "text": message(notification) }),
+		WebhookFormat::Matrix => json!({ "msgtype": "m.text", "body": message(notification) }),
+	};
+
+	let timeout = Duration::from_secs(notifier.webhook_timeout.unwrap_or(DEFAULT_WEBHOOK_TIMEOUT));
+	let agent = ureq::AgentBuilder::new().timeout_connect(timeout).timeout_read(timeout).build();
+
+	agent
+		.post(&notifier.webhook_url)
+		.set("Content-Type", "application/json")
+		.send_string(&payload.to_string())
+		.map(|_| ())
+		.map_err(|e| {
+			log::error!(target: LOG_TARGET, "Webhook request failed: {:?}", e);
+			NotifierError::WebhookFailed
+		})
+}
+
+fn message(notification: &Notification) -> String {
+	let (relay_chain, para_id) = &notification.para;
+	let para = format!("{} ({}-{})", notification.name, relay_chain, para_id);
+
+	match notification.kind {
+		NotificationKind::RenewalWindowOpened => format!(
+			"The subscription of {} expires at {} and can now be renewed.",
+			para, notification.expiry_timestamp
+		),
+		NotificationKind::Expired =>
+			format!("The subscription of {} expired at {}.", para, notification.expiry_timestamp),
+		NotificationKind::GracePeriodEnded => format!(
+			"The grace period of the subscription of {} which expired at {} has ended.",
+			para, notification.expiry_timestamp
+		),
+	}
+}
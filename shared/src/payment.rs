@@ -16,27 +16,56 @@
 //! File containing all the payment validation related logic.
 
 use crate::{
-	config::PaymentInfo,
-	payment::polkadot::runtime_types::{
-		frame_system::pallet::Call as SystemCall, pallet_balances::pallet::Call as BalancesCall,
-		pallet_utility::pallet::Call as UtilityCall,
-	},
+	config::{AssetId, Currency, PaymentInfo, Transfer},
 	*,
 };
-use parity_scale_codec::Encode;
 use polkadot_core_primitives::BlockNumber;
 use serde::{Deserialize, Serialize};
 use subxt::{
 	backend::rpc::{rpc_params, RpcClient},
-	blocks::Block,
-	utils::H256,
+	blocks::{Block, ExtrinsicDetails, ExtrinsicEvents},
+	config::{substrate::BlakeTwo256, Hasher},
+	events::StaticEvent,
+	ext::{
+		scale_decode::DecodeAsType,
+		scale_value::{Composite, Value, ValueDef},
+	},
+	utils::{AccountId32, H256},
 	OnlineClient, PolkadotConfig,
 };
-use types::Parachain;
+use types::{ParaId, Parachain, RelayChain};
+
+use crate::{
+	extrinsic::{Call, ExtrinsicSummary},
+	ledger::{is_used, PaymentId, PaymentReceipt, PaymentReference},
+	watcher::{remarked_para, DetectedPayment},
+};
+
+type PaymentBlock = Block<PolkadotConfig, OnlineClient<PolkadotConfig>>;
+type PaymentExtrinsic = ExtrinsicDetails<PolkadotConfig, OnlineClient<PolkadotConfig>>;
 
 #[subxt::subxt(runtime_metadata_path = "../artifacts/metadata.scale")]
 mod polkadot {}
 
+/// The `Assets::Transferred` event.
+///
+/// Defined manually since the `Assets` pallet isn't part of the relay chain metadata, while the
+/// event is the same on all the chains that have the pallet, e.g. Asset Hub.
+#[derive(Debug, DecodeAsType)]
+#[decode_as_type(crate_path = "subxt::ext::scale_decode")]
+struct AssetTransferred {
+	asset_id: AssetId,
+	#[allow(dead_code)]
+	from: AccountId32,
+	to: AccountId32,
+	amount: u128,
+}
+
+impl StaticEvent for AssetTransferred {
+	const PALLET: &'static str = "Assets";
+	const EVENT: &'static str = "Transferred";
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub enum PaymentError {
 	/// Failed to validate they payment.
@@ -45,24 +74,19 @@ pub enum PaymentError {
 	Unfinalized,
 	/// The payment was not found in the specified block.
 	NotFound,
+	/// The payment was already used.
+	AlreadyUsed,
+	/// The payment extrinsic didn't succeed.
+	Failed,
 }
 
-impl From<String> for PaymentError {
-	fn from(v: String) -> Self {
-		match v.as_str() {
-			"ValidationFailed" => Self::ValidationFailed,
-			"Unfinalized" => Self::Unfinalized,
-			"NotFound" => Self::NotFound,
-			_ => panic!("UnknownError"),
-		}
-	}
-}
-
+/// Ensures that the referenced extrinsic is a successful payment for the parachain, and returns
+/// the receipt of the payment.
 pub async fn validate_registration_payment(
 	para: Parachain,
 	payment_info: PaymentInfo,
-	payment_block_number: BlockNumber,
-) -> Result<(), PaymentError> {
+	reference: PaymentReference,
+) -> Result<PaymentReceipt, PaymentError> {
 	// TODO: Could this code be improved so that we don't have to instantiate both clients?
 	let rpc_client = RpcClient::from_url(&payment_info.rpc_url.clone())
 		.await
@@ -72,72 +96,219 @@ pub async fn validate_registration_payment(
 		.await
 		.map_err(|_| PaymentError::ValidationFailed)?;
 
-	// Ensure that the `payment_block_number` is from a finalized block.
+	// Ensure that the payment is part of a finalized block.
 	let last_finalized =
 		get_last_finalized_block(rpc_client.clone(), online_client.clone()).await?;
-	if payment_block_number > last_finalized {
+	if reference.block_number() > last_finalized {
 		return Err(PaymentError::Unfinalized);
 	}
 
-	let block_hash = get_block_hash(rpc_client, payment_block_number).await?;
-	let block = get_block(online_client, block_hash).await?;
+	let block_hash = get_block_hash(rpc_client, reference.block_number()).await?;
+	let block = get_block(online_client.clone(), block_hash).await?;
+
+	let extrinsic = find_extrinsic(block, &reference).await?;
+	let events = extrinsic.events().await.map_err(|_| PaymentError::ValidationFailed)?;
 
-	ensure_contains_payment(para, payment_info, block).await
+	let (currency, amount) = summary(decoded_call(&extrinsic)?, &events)?
+		.payment_for(&(para.relay_chain, para.para_id), &payment_info)?;
+
+	let receipt = payment_receipt(
+		&online_client,
+		block_hash,
+		reference.block_number(),
+		(currency, amount),
+		&extrinsic,
+		&events,
+	);
+
+	if is_used(&receipt.payment)? {
+		return Err(PaymentError::AlreadyUsed);
+	}
+
+	Ok(receipt)
 }
 
-async fn ensure_contains_payment(
-	para: Parachain,
-	payment_info: PaymentInfo,
-	block: Block<PolkadotConfig, OnlineClient<PolkadotConfig>>,
-) -> Result<(), PaymentError> {
-	let payment = opaque_payment_extrinsic(para, payment_info).await?;
+/// Returns all the successful payments made in the block.
+///
+/// Used by the payment watcher, which doesn't know the paid parachains upfront, so they are
+/// determined from the remarks made by the extrinsics.
+pub async fn block_payments(
+	online_client: &OnlineClient<PolkadotConfig>,
+	block: PaymentBlock,
+	payment_info: &PaymentInfo,
+) -> Result<Vec<DetectedPayment>, PaymentError> {
+	let extrinsics = block.extrinsics().await.map_err(|_| PaymentError::ValidationFailed)?;
+
+	let mut payments = vec![];
+	for extrinsic in extrinsics.iter().filter_map(|ext| ext.ok()) {
+		// The extrinsics which can't be decoded can't be payments either.
+		let Ok(call) = decoded_call(&extrinsic) else {
+			continue;
+		};
+		let Some(para) = call.remarks().into_iter().find_map(remarked_para) else {
+			continue;
+		};
+
+		let events = extrinsic.events().await.map_err(|_| PaymentError::ValidationFailed)?;
+		let Ok(paid) = summary(call, &events)?.payment_for(&para, payment_info) else {
+			continue;
+		};
+
+		let receipt =
+			payment_receipt(online_client, block.hash(), block.number(), paid, &extrinsic, &events);
+		payments.push(DetectedPayment { para, receipt });
+	}
+
+	Ok(payments)
+}
+
+/// Returns all the successful payments made in the block with the given number.
+pub async fn block_payments_at(
+	rpc_client: &RpcClient,
+	online_client: &OnlineClient<PolkadotConfig>,
+	block_number: BlockNumber,
+	payment_info: &PaymentInfo,
+) -> Result<Vec<DetectedPayment>, PaymentError> {
+	let block_hash = get_block_hash(rpc_client.clone(), block_number).await?;
+	let block = get_block(online_client.clone(), block_hash).await?;
 
+	block_payments(online_client, block, payment_info).await
+}
+
+/// Returns the extrinsic referenced by `reference` from the block.
+async fn find_extrinsic(
+	block: PaymentBlock,
+	reference: &PaymentReference,
+) -> Result<PaymentExtrinsic, PaymentError> {
 	let extrinsics = block.extrinsics().await.map_err(|_| PaymentError::ValidationFailed)?;
-	let extrinsics: Vec<Vec<u8>> = extrinsics
-		.iter()
-		.filter_map(|ext| {
-			ext.as_ref().ok().and_then(|e| e.as_root_extrinsic::<polkadot::Call>().ok())
-		})
-		.map(|ext| ext.encode())
-		.collect();
-
-	if extrinsics.contains(&payment.encode()) {
-		Ok(())
-	} else {
-		Err(PaymentError::NotFound)
+
+	let found = extrinsics.iter().filter_map(|ext| ext.ok()).find(|ext| match reference {
+		PaymentReference::Index { index, .. } => ext.index() == *index,
+		// The hash of an extrinsic is the hash of its SCALE encoded bytes.
+		PaymentReference::Hash { hash, .. } =>
+			format!("{:?}", BlakeTwo256::hash_of(&ext.bytes())).eq_ignore_ascii_case(hash),
+	});
+
+	found.ok_or(PaymentError::NotFound)
+}
+
+/// Decodes the call of the extrinsic along with the calls it dispatches.
+fn decoded_call(extrinsic: &PaymentExtrinsic) -> Result<Call, PaymentError> {
+	let pallet = extrinsic.pallet_name().map_err(|_| PaymentError::ValidationFailed)?;
+	let name = extrinsic.variant_name().map_err(|_| PaymentError::ValidationFailed)?;
+	let fields = extrinsic.field_values().map_err(|_| PaymentError::ValidationFailed)?;
+
+	Ok(call(pallet, name, &fields))
+}
+
+/// Builds the call from its decoded fields.
+///
+/// The dispatched calls are the values of the `call` and `calls` fields, as used by the
+/// `Utility`, `Proxy`, `Multisig` and `Sudo` pallets.
+fn call<T>(pallet: &str, name: &str, fields: &Composite<T>) -> Call {
+	if pallet == "System" && matches!(name, "remark" | "remark_with_event") {
+		let remark = field(fields, "remark").map(sequence).unwrap_or_default();
+		return Call::Remark(
+			remark
+				.into_iter()
+				.filter_map(|byte| byte.as_u128().and_then(|byte| u8::try_from(byte).ok()))
+				.collect(),
+		);
+	}
+
+	let mut calls: Vec<Call> = field(fields, "call").and_then(dispatched).into_iter().collect();
+	if let Some(batch) = field(fields, "calls") {
+		calls.extend(sequence(batch).into_iter().filter_map(dispatched));
 	}
+
+	Call::Other(calls)
 }
 
-async fn opaque_payment_extrinsic(
-	para: Parachain,
-	payment_info: PaymentInfo,
-) -> Result<polkadot::Call, PaymentError> {
-	if let Ok(cost) = payment_info.cost.parse::<u128>() {
-		let transfer_call = polkadot::Call::Balances(BalancesCall::transfer_keep_alive {
-			dest: payment_info.receiver.into(),
-			value: cost,
-		});
+/// Builds a dispatched call, which is encoded as the variant of its pallet wrapping the variant of
+/// the call itself.
+fn dispatched<T>(value: &Value<T>) -> Option<Call> {
+	let ValueDef::Variant(pallet) = &value.value else {
+		return None;
+	};
+	let ValueDef::Variant(dispatched) = &pallet.values.values().next()?.value else {
+		return None;
+	};
+
+	Some(call(&pallet.name, &dispatched.name, &dispatched.values))
+}
+
+fn field<'a, T>(fields: &'a Composite<T>, name: &str) -> Option<&'a Value<T>> {
+	match fields {
+		Composite::Named(fields) =>
+			fields.iter().find(|(field_name, _)| field_name == name).map(|(_, value)| value),
+		Composite::Unnamed(_) => None,
+	}
+}
 
-		let remark = format!("regionx-weigher::{}:{}", para.relay_chain, para.para_id)
-			.as_bytes()
-			.to_vec();
-		let remark_call = polkadot::Call::System(SystemCall::remark { remark });
+/// The values of a sequence, e.g. the bytes of a `Vec<u8>`.
+fn sequence<T>(value: &Value<T>) -> Vec<&Value<T>> {
+	match &value.value {
+		ValueDef::Composite(values) => values.values().collect(),
+		_ => vec![],
+	}
+}
 
-		let batch_call = polkadot::Call::Utility(UtilityCall::batch_all {
-			calls: vec![transfer_call, remark_call],
+/// Summarizes the call and the events of the extrinsic.
+fn summary(
+	call: Call,
+	events: &ExtrinsicEvents<PolkadotConfig>,
+) -> Result<ExtrinsicSummary, PaymentError> {
+	let native_transfers = events
+		.find::<polkadot::balances::events::Transfer>()
+		.filter_map(|event| event.ok())
+		.map(|transfer| Transfer {
+			currency: Currency::Native,
+			to: transfer.to,
+			amount: transfer.amount,
 		});
+	let asset_transfers =
+		events
+			.find::<AssetTransferred>()
+			.filter_map(|event| event.ok())
+			.map(|transfer| Transfer {
+				currency: Currency::Asset(transfer.asset_id),
+				to: transfer.to,
+				amount: transfer.amount,
+			});
 
-		Ok(batch_call)
-	} else {
-		log::error!(
-			target: LOG_TARGET,
-			"Failed to parse cost",
-		);
-		Err(PaymentError::ValidationFailed)
+	let succeeded = events
+		.has::<polkadot::system::events::ExtrinsicSuccess>()
+		.map_err(|_| PaymentError::ValidationFailed)?;
+
+	Ok(ExtrinsicSummary {
+		call,
+		transfers: native_transfers.chain(asset_transfers).collect(),
+		succeeded,
+	})
+}
+
+fn payment_receipt(
+	online_client: &OnlineClient<PolkadotConfig>,
+	block_hash: H256,
+	block_number: BlockNumber,
+	(currency, amount): (Currency, u128),
+	extrinsic: &PaymentExtrinsic,
+	events: &ExtrinsicEvents<PolkadotConfig>,
+) -> PaymentReceipt {
+	PaymentReceipt {
+		payment: PaymentId {
+			chain: format!("{:?}", online_client.genesis_hash()),
+			block_hash: format!("{:?}", block_hash),
+			extrinsic_index: extrinsic.index(),
+		},
+		block_number,
+		extrinsic_hash: format!("{:?}", events.extrinsic_hash()),
+		amount,
+		currency,
 	}
 }
 
-async fn get_last_finalized_block(
+pub async fn get_last_finalized_block(
 	rpc_client: RpcClient,
 	online_client: OnlineClient<PolkadotConfig>,
 ) -> Result<BlockNumber, PaymentError> {
@@ -155,7 +326,7 @@ async fn get_last_finalized_block(
 async fn get_block(
 	api: OnlineClient<PolkadotConfig>,
 	block_hash: H256,
-) -> Result<Block<PolkadotConfig, OnlineClient<PolkadotConfig>>, PaymentError> {
+) -> Result<PaymentBlock, PaymentError> {
 	api.blocks().at(block_hash).await.map_err(|_| PaymentError::ValidationFailed)
 }
 
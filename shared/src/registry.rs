@@ -13,59 +13,216 @@
 // You should have received a copy of the GNU General Public License
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::config::config;
+//! The registry of the parachains for which the consumption is tracked.
+//!
+//! All the updates are read-modify-write operations performed while holding an exclusive lock on
+//! a separate lock file, so concurrent updates can't overwrite each other. The new content is
+//! written to a temporary file which is then renamed into place, so readers never observe a
+//! partially written registry and don't need to take the lock.
+//!
+//! Every modification is recorded in the audit log once the registry is written. Should recording
+//! it fail, the previous registry is restored, so the registry never contains changes missing from
+//! the audit log. Payments used by a modification are recorded in the ledger before the audit log,
+//! so a modification reverted because of the ledger doesn't leave entries in the audit log.
+
+use crate::{
+	audit::{audit_entries, record, ChangeOrigin},
+	config::config,
+	ledger::{consume_payment, is_used, release_payment, PaymentReceipt},
+	payment::PaymentError,
+	write_atomically, LOG_TARGET,
+};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
 use std::{
 	fs::{File, OpenOptions},
-	io::{Read, Seek, Write},
+	io::ErrorKind,
 };
 use types::{ParaId, Parachain, RelayChain};
 
-pub fn registered_paras() -> Vec<Parachain> {
-	let mut registry = get_registry();
-	let mut content = String::new();
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub enum RegistryError {
+	/// Failed to acquire the registry lock.
+	LockFailed,
+	/// Failed to read the registry file.
+	ReadFailed,
+	/// The content of the registry file is invalid.
+	InvalidRegistry,
+	/// Failed to write the registry file.
+	WriteFailed,
+	/// Failed to read or write the audit log.
+	AuditFailed,
+}
 
-	// If this fails it simply means that the registry is empty.
-	let _ = registry.read_to_string(&mut content);
-	let paras: Vec<Parachain> = serde_json::from_str(&content).expect("Failed to serialize");
+/// Returns all the registered parachains.
+///
+/// A missing registry file is treated as an empty registry.
+pub fn registered_paras() -> Result<Vec<Parachain>, RegistryError> {
+	let content = match std::fs::read_to_string(config().registry) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => {
+			log::error!(target: LOG_TARGET, "Failed to read the registry: {:?}", e);
+			return Err(RegistryError::ReadFailed);
+		},
+	};
 
-	paras
+	serde_json::from_str(&content).map_err(|e| {
+		log::error!(target: LOG_TARGET, "Failed to parse the registry: {:?}", e);
+		RegistryError::InvalidRegistry
+	})
 }
 
-pub fn registered_para(relay_chain: RelayChain, para_id: ParaId) -> Option<Parachain> {
-	registered_paras()
-		.iter()
-		.find(|para| para.relay_chain == relay_chain && para.para_id == para_id)
-		.cloned()
+pub fn registered_para(
+	relay_chain: RelayChain,
+	para_id: ParaId,
+) -> Result<Option<Parachain>, RegistryError> {
+	Ok(registered_paras()?
+		.into_iter()
+		.find(|para| para.relay_chain == relay_chain && para.para_id == para_id))
 }
 
-pub fn update_registry(paras: Vec<Parachain>) -> Result<(), String> {
-	let mut registry = get_registry();
-	let json_data = serde_json::to_string_pretty(&paras).map_err(|_| "Failed to serialize")?;
+/// Replaces the content of the registry.
+///
+/// The change isn't recorded in the audit log, so this should only be used for initialisation.
+pub fn update_registry(paras: Vec<Parachain>) -> Result<(), RegistryError> {
+	let _lock = lock_registry()?;
+	write_registry(&paras)
+}
 
-	registry.set_len(0).map_err(|_| "Failed to truncate file")?;
-	registry
-		.seek(std::io::SeekFrom::Start(0))
-		.map_err(|_| "Failed to seek to the beginning")?;
+/// Atomically modifies the registry and records the changes in the audit log.
+///
+/// The registry is locked for the whole read-modify-write cycle. If `f` returns an error the
+/// registry is left untouched.
+pub fn modify_registry<T, E, F>(origin: &ChangeOrigin, f: F) -> Result<T, E>
+where
+	F: FnOnce(&mut Vec<Parachain>) -> Result<T, E>,
+	E: From<RegistryError>,
+{
+	// The lock is released once `_lock` gets dropped.
+	let _lock = lock_registry()?;
 
-	registry
-		.write_all(json_data.as_bytes())
-		.map_err(|_| "Failed to write into file")?;
+	let old = registered_paras()?;
+	let mut paras = old.clone();
+	let result = f(&mut paras)?;
+	commit(&old, &paras, origin)?;
 
-	Ok(())
+	Ok(result)
 }
 
-fn get_registry() -> File {
-	match OpenOptions::new().read(true).write(true).open(config().registry) {
-		Ok(file) => file,
-		Err(_) => init_registry(),
+/// Like [`modify_registry`], with `f` also returning the payment used for the modification.
+///
+/// The payment is recorded in the ledger while still holding the lock, but only once the registry
+/// is written, so a failed modification never uses up the payment. Should recording the payment
+/// fail, the old registry is restored without recording anything in the audit log. Should
+/// recording the changes in the audit log fail, the payment is released again.
+pub fn modify_registry_with_payment<T, E, F>(origin: &ChangeOrigin, f: F) -> Result<T, E>
+where
+	F: FnOnce(&mut Vec<Parachain>) -> Result<(T, Option<UsedPayment>), E>,
+	E: From<RegistryError> + From<PaymentError>,
+{
+	// The lock is released once `_lock` gets dropped.
+	let _lock = lock_registry()?;
+
+	let old = registered_paras()?;
+	let mut paras = old.clone();
+	let (result, payment) = f(&mut paras)?;
+
+	if let Some((receipt, _)) = &payment {
+		if is_used(&receipt.payment)? {
+			return Err(PaymentError::AlreadyUsed.into());
+		}
 	}
+
+	write_registry(&paras)?;
+
+	let payment = match payment {
+		Some((receipt, para)) => {
+			let id = receipt.payment.clone();
+			if let Err(e) = consume_payment(receipt, para) {
+				restore_registry(&old);
+				return Err(e.into());
+			}
+			Some(id)
+		},
+		None => None,
+	};
+
+	if let Err(e) = record_changes(&old, &paras, origin) {
+		if let Some(id) = payment {
+			if let Err(e) = release_payment(&id) {
+				log::error!(target: LOG_TARGET, "Failed to release the payment: {:?}", e);
+			}
+		}
+		return Err(e.into());
+	}
+
+	Ok(result)
 }
 
-pub fn init_registry() -> File {
-	let mut registry =
-		File::create(config().registry).expect("Failed to create registered para file");
-	// An empty vector
-	registry.write_all(b"[]").expect("Failed to write into registered para file");
+/// A payment along with the parachain for which it is used.
+pub type UsedPayment = (PaymentReceipt, (RelayChain, ParaId));
+
+/// Resets the registry to an empty one.
+pub fn init_registry() -> Result<(), RegistryError> {
+	update_registry(vec![])
+}
+
+/// Writes the new registry and records the changes from `old` to `new` in the audit log.
+///
+/// The old registry is restored if the changes can't be recorded.
+fn commit(
+	old: &[Parachain],
+	new: &[Parachain],
+	origin: &ChangeOrigin,
+) -> Result<(), RegistryError> {
+	write_registry(new)?;
+	record_changes(old, new, origin)
+}
+
+/// Records the changes from `old` to `new` in the audit log, restoring the old registry on failure.
+fn record_changes(
+	old: &[Parachain],
+	new: &[Parachain],
+	origin: &ChangeOrigin,
+) -> Result<(), RegistryError> {
+	record(&audit_entries(old, new, origin)).map_err(|e| {
+		log::error!(target: LOG_TARGET, "Failed to record registry changes: {:?}", e);
+		restore_registry(old);
+		RegistryError::AuditFailed
+	})
+}
+
+fn restore_registry(old: &[Parachain]) {
+	if let Err(e) = write_registry(old) {
+		log::error!(target: LOG_TARGET, "Failed to restore the registry: {:?}", e);
+	}
+}
+
+fn write_registry(paras: &[Parachain]) -> Result<(), RegistryError> {
+	let json_data =
+		serde_json::to_string_pretty(paras).map_err(|_| RegistryError::InvalidRegistry)?;
+
+	write_atomically(&config().registry, json_data.as_bytes()).map_err(|e| {
+		log::error!(target: LOG_TARGET, "Failed to write the registry: {:?}", e);
+		RegistryError::WriteFailed
+	})
+}
+
+/// Blocks until the exclusive lock of the registry is acquired. The lock is held until the
+/// returned file is dropped.
+fn lock_registry() -> Result<File, RegistryError> {
+	let lock = OpenOptions::new()
+		.create(true)
+		.truncate(false)
+		.write(true)
+		.open(format!("{}.lock", config().registry))
+		.map_err(|_| RegistryError::LockFailed)?;
+
+	lock.lock_exclusive().map_err(|e| {
+		log::error!(target: LOG_TARGET, "Failed to lock the registry: {:?}", e);
+		RegistryError::LockFailed
+	})?;
 
-	registry
+	Ok(lock)
 }
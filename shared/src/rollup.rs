@@ -0,0 +1,182 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Precomputed aggregates of the per-block consumption data.
+//!
+//! For every parachain a separate rollup file is kept for each `RollupPeriod`. Each row contains
+//! the mean, min and max consumption per dispatch class together with the number of blocks that
+//! were produced within the period, followed by the 50th, 90th and 99th percentiles, the range
+//! of blocks within the period and the total consumption per dispatch class.
+
+use crate::{config::rollup_directory, consumption::csv_content, write_atomically, LOG_TARGET};
+use csv::ReaderBuilder;
+use std::{collections::BTreeMap, fs::File};
+use types::{
+	ConsumptionPercentiles, ConsumptionRollup, ConsumptionStats, DispatchClassConsumption,
+	DispatchClassPercentiles, DispatchClassStats, Parachain, RollupPeriod, Timestamp,
+	WeightConsumption,
+};
+
+pub fn get_rollups(
+	para: Parachain,
+	period: RollupPeriod,
+) -> Result<Vec<ConsumptionRollup>, &'static str> {
+	let file = File::open(rollup_file_path(para, period)).map_err(|_| "Rollup data not found")?;
+	let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(file);
+
+	let rollups: Vec<ConsumptionRollup> = rdr
+		.deserialize::<ConsumptionRollup>()
+		.filter_map(|result| result.ok())
+		.collect();
+
+	Ok(rollups)
+}
+
+pub fn write_rollups(
+	para: Parachain,
+	period: RollupPeriod,
+	rollups: Vec<ConsumptionRollup>,
+) -> Result<(), std::io::Error> {
+	log::info!(
+		target: LOG_TARGET,
+		"{}-{} - Writing {} rollups.",
+		para.relay_chain, para.para_id, period
+	);
+
+	std::fs::create_dir_all(rollup_directory())?;
+
+	let content = csv_content(rollups.iter().map(|rollup| rollup.to_csv()).collect())?;
+	write_atomically(&rollup_file_path(para, period), &content)
+}
+
+/// Recomputes the rollups for all the periods covered by `consumption`.
+///
+/// Previously computed rollups of periods for which there is no consumption data anymore are
+/// kept, which allows the per-block data to be pruned once it has been rolled up.
+pub fn update_rollups(
+	para: Parachain,
+	period: RollupPeriod,
+	consumption: &[WeightConsumption],
+) -> Result<(), std::io::Error> {
+	let mut rollups: BTreeMap<Timestamp, ConsumptionRollup> = get_rollups(para.clone(), period)
+		.unwrap_or_default()
+		.into_iter()
+		.map(|rollup| (rollup.period_start, rollup))
+		.collect();
+
+	compute_rollups(consumption, period).into_iter().for_each(|rollup| {
+		rollups.insert(rollup.period_start, rollup);
+	});
+
+	write_rollups(para, period, rollups.into_values().collect())
+}
+
+pub fn compute_rollups(
+	consumption: &[WeightConsumption],
+	period: RollupPeriod,
+) -> Vec<ConsumptionRollup> {
+	let grouped = consumption.iter().fold(BTreeMap::new(), |mut acc, datum| {
+		let entry: &mut Vec<&WeightConsumption> =
+			acc.entry(period.period_start(datum.timestamp)).or_default();
+		entry.push(datum);
+		acc
+	});
+
+	grouped
+		.into_iter()
+		.map(|(period_start, data)| ConsumptionRollup {
+			period_start,
+			count: data.len(),
+			ref_time: dispatch_class_stats(data.iter().map(|datum| &datum.ref_time)),
+			proof_size: dispatch_class_stats(data.iter().map(|datum| &datum.proof_size)),
+			ref_time_percentiles: dispatch_class_percentiles(
+				data.iter().map(|datum| &datum.ref_time),
+			),
+			proof_size_percentiles: dispatch_class_percentiles(
+				data.iter().map(|datum| &datum.proof_size),
+			),
+			first_block: data.iter().map(|datum| datum.block_number).min().unwrap_or_default(),
+			last_block: data.iter().map(|datum| datum.block_number).max().unwrap_or_default(),
+			ref_time_sum: dispatch_class_sum(data.iter().map(|datum| &datum.ref_time)),
+			proof_size_sum: dispatch_class_sum(data.iter().map(|datum| &datum.proof_size)),
+		})
+		.collect()
+}
+
+fn dispatch_class_stats<'a>(
+	consumption: impl Iterator<Item = &'a DispatchClassConsumption> + Clone,
+) -> DispatchClassStats {
+	DispatchClassStats {
+		normal: stats(consumption.clone().map(|c| c.normal)),
+		operational: stats(consumption.clone().map(|c| c.operational)),
+		mandatory: stats(consumption.map(|c| c.mandatory)),
+	}
+}
+
+fn dispatch_class_sum<'a>(
+	consumption: impl Iterator<Item = &'a DispatchClassConsumption>,
+) -> DispatchClassConsumption {
+	consumption.fold(DispatchClassConsumption::default(), |mut sum, datum| {
+		sum.normal += datum.normal;
+		sum.operational += datum.operational;
+		sum.mandatory += datum.mandatory;
+		sum
+	})
+}
+
+fn stats(values: impl Iterator<Item = f64>) -> ConsumptionStats {
+	let (sum, min, max, count) = values
+		.fold((0f64, f64::MAX, f64::MIN, 0usize), |(sum, min, max, count), value| {
+			(sum + value, min.min(value), max.max(value), count + 1)
+		});
+
+	if count == 0 {
+		return Default::default();
+	}
+
+	ConsumptionStats { mean: sum / count as f64, min, max }
+}
+
+fn dispatch_class_percentiles<'a>(
+	consumption: impl Iterator<Item = &'a DispatchClassConsumption> + Clone,
+) -> DispatchClassPercentiles {
+	DispatchClassPercentiles {
+		normal: percentiles(consumption.clone().map(|c| c.normal)),
+		operational: percentiles(consumption.clone().map(|c| c.operational)),
+		mandatory: percentiles(consumption.map(|c| c.mandatory)),
+	}
+}
+
+fn percentiles(values: impl Iterator<Item = f64>) -> ConsumptionPercentiles {
+	let mut values: Vec<f64> = values.collect();
+	values.sort_by(f64::total_cmp);
+
+	ConsumptionPercentiles {
+		p50: percentile(&values, 50),
+		p90: percentile(&values, 90),
+		p99: percentile(&values, 99),
+	}
+}
+
+/// Returns the given percentile of values sorted in ascending order, using the nearest-rank
+/// method. Returns zero if there are no values.
+pub fn percentile(sorted: &[f64], percentile: usize) -> f64 {
+	let rank = (percentile * sorted.len()).div_ceil(100);
+	sorted.get(rank.saturating_sub(1)).copied().unwrap_or_default()
+}
+
+fn rollup_file_path(para: Parachain, period: RollupPeriod) -> String {
+	format!("{}/{}-{}-{}.csv", rollup_directory(), para.relay_chain, para.para_id, period)
+}
@@ -0,0 +1,378 @@
+// This file is part of RegionX.
+//
+// RegionX is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// RegionX is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Applies the payments detected by the payment watcher to the registry.
+//!
+//! A payment is attributed to a parachain through the remark made by the payment extrinsic, see
+//! [`payment_remark`].
+//!
+//! The last processed block of each payment chain is stored in the `watcher_state` file, so that
+//! the blocks finalized while the watcher was down or disconnected get processed once it is back.
+
+use crate::{
+	audit::ChangeOrigin,
+	chaindata::{get_para, ChainDataError},
+	config::{config, PaymentInfo},
+	current_timestamp,
+	ledger::PaymentReceipt,
+	payment::PaymentError,
+	registry::{modify_registry_with_payment, RegistryError},
+	write_atomically, LOG_TARGET,
+};
+use parity_scale_codec::Encode;
+use polkadot_core_primitives::BlockNumber;
+use std::{collections::HashMap, future::Future, io::ErrorKind, sync::Mutex, time::Duration};
+use types::{ParaId, Parachain, RelayChain};
+
+/// The actor recorded in the audit log for the changes made by the payment watcher.
+pub const WATCHER_ACTOR: &str = "payment-watcher";
+
+const REMARK_PREFIX: &str = "regionx-weigher::";
+
+/// The delay before the first attempt to reconnect to the payment chain.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// The maximum delay between two attempts to reconnect to the payment chain.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Serialises the updates of the watcher state made by the watchers of different payment chains.
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// A payment for a parachain found on the payment chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DetectedPayment {
+	/// The parachain for which the payment was made.
+	pub para: (RelayChain, ParaId),
+	pub receipt: PaymentReceipt,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum WatcherError {
+	/// The paid parachain couldn't be resolved from the chaindata.
+	ChainData(ChainDataError),
+	/// The payment couldn't be used.
+	Payment(PaymentError),
+	/// The payment doesn't buy a subscription, e.g. because the amount doesn't cover a single
+	/// period or the currency isn't accepted.
+	InsufficientPayment,
+	/// Failed to update the registry.
+	Registry(RegistryError),
+	/// Failed to query the payment chain.
+	Rpc,
+	/// Failed to read or write the last processed block.
+	State,
+}
+
+impl WatcherError {
+	/// Whether the payment can never be applied, in which case it is skipped rather than retried.
+	///
+	/// The other errors, e.g. failing to write the registry or the ledger, may go away, so the
+	/// block containing the payment is processed again.
+	pub fn is_permanent(&self) -> bool {
+		matches!(
+			self,
+			Self::ChainData(ChainDataError::ParaNotFound) |
+				Self::InsufficientPayment |
+				Self::Payment(PaymentError::AlreadyUsed)
+		)
+	}
+}
+
+impl From<RegistryError> for WatcherError {
+	fn from(v: RegistryError) -> Self {
+		Self::Registry(v)
+	}
+}
+
+impl From<PaymentError> for WatcherError {
+	fn from(v: PaymentError) -> Self {
+		Self::Payment(v)
+	}
+}
+
+/// The remark identifying the parachain for which the payment is made.
+pub fn payment_remark(para: &(RelayChain, ParaId)) -> Vec<u8> {
+	format!("{}{}:{}", REMARK_PREFIX, para.0, para.1).into_bytes()
+}
+
+/// The SCALE encoded `System::remark_with_event` call making the remark of the parachain.
+///
+/// The `System` pallet and the call have the same index on all the chains.
+pub fn remark_call(para: &(RelayChain, ParaId)) -> Vec<u8> {
+	const SYSTEM_PALLET_INDEX: u8 = 0;
+	const REMARK_WITH_EVENT_CALL_INDEX: u8 = 7;
+
+	(SYSTEM_PALLET_INDEX, REMARK_WITH_EVENT_CALL_INDEX, payment_remark(para)).encode()
+}
+
+/// Returns the parachain identified by the remark, if it is the remark of a parachain.
+pub fn remarked_para(remark: &[u8]) -> Option<(RelayChain, ParaId)> {
+	let remark = std::str::from_utf8(remark).ok()?.strip_prefix(REMARK_PREFIX)?;
+	let (relay_chain, para_id) = remark.split_once(':')?;
+
+	Some((relay_chain.parse().ok()?, para_id.parse().ok()?))
+}
+
+/// Applies a detected payment to the registry and records it in the ledger.
+///
+/// An unregistered parachain gets registered, while the subscription of a registered one is
+/// extended by the duration bought with the payment. An expired subscription is extended from now
+/// on.
+///
+/// Returns the parachain as stored in the registry.
+pub fn apply_payment(
+	payment: DetectedPayment,
+	payment_info: &PaymentInfo,
+) -> Result<Parachain, WatcherError> {
+	let (relay_chain, para_id) = payment.para.clone();
+	let subscription = payment_info
+		.subscription(payment.receipt.currency, payment.receipt.amount)
+		.ok_or(WatcherError::InsufficientPayment)?;
+	let origin = ChangeOrigin {
+		actor: WATCHER_ACTOR.into(),
+		payment_block_number: Some(payment.receipt.block_number),
+	};
+
+	modify_registry_with_payment(&origin, |paras| -> Result<_, WatcherError> {
+		let now = current_timestamp();
+
+		let para =
+			match paras.iter_mut().find(|p| p.relay_chain == relay_chain && p.para_id == para_id) {
+				Some(para) => {
+					subscription.extend(para, now);
+					para.clone()
+				},
+				None => {
+					let mut para =
+						get_para(relay_chain.clone(), para_id).map_err(WatcherError::ChainData)?;
+					para.expiry_timestamp = now.saturating_add(subscription.duration);
+					para.plan = subscription.plan.clone();
+					paras.push(para.clone());
+					para
+				},
+			};
+
+		Ok((para, Some((payment.receipt.clone(), payment.para.clone()))))
+	})
+	.map_err(|err| {
+		log::error!(
+			target: LOG_TARGET,
+			"{}-{} - Failed to apply payment: {:?}",
+			relay_chain,
+			para_id,
+			err
+		);
+		err
+	})
+}
+
+/// The payment chain as followed by the payment watcher.
+pub trait PaymentChain {
+	/// Returns the number of the latest finalized block.
+	fn finalized_block(&self) -> impl Future<Output = Result<BlockNumber, WatcherError>> + Send;
+
+	/// Waits for the next finalized block and returns its number, or `None` once the subscription
+	/// ended.
+	fn next_finalized_block(
+		&mut self,
+	) -> impl Future<Output = Option<Result<BlockNumber, WatcherError>>> + Send;
+
+	/// Returns the payments made in the finalized block with the given number.
+	fn payments_at(
+		&self,
+		block_number: BlockNumber,
+	) -> impl Future<Output = Result<Vec<DetectedPayment>, WatcherError>> + Send;
+}
+
+/// Follows the payment chain forever, reconnecting with an exponential backoff whenever the
+/// connection fails or the subscription ends.
+pub async fn watch_payments<C, F, Fut>(
+	relay_chain: RelayChain,
+	payment_info: PaymentInfo,
+	connect: F,
+) where
+	C: PaymentChain,
+	F: Fn() -> Fut,
+	Fut: Future<Output = Result<C, WatcherError>>,
+{
+	let mut attempt = 0;
+	loop {
+		let last_processed = last_processed_block(&relay_chain).ok().flatten();
+
+		let result = match connect().await {
+			Ok(mut chain) => follow_payments(&mut chain, &relay_chain, &payment_info).await,
+			Err(err) => Err(err),
+		};
+
+		// Only back off further if no progress could be made since the last attempt.
+		if last_processed_block(&relay_chain).ok().flatten() != last_processed {
+			attempt = 0;
+		}
+
+		let delay = retry_delay(attempt);
+		log::error!(
+			target: LOG_TARGET,
+			"{} - Stopped watching payments: {:?}, reconnecting in {}s",
+			relay_chain,
+			result,
+			delay.as_secs()
+		);
+
+		tokio::time::sleep(delay).await;
+		attempt = attempt.saturating_add(1);
+	}
+}
+
+/// Returns the delay before the given attempt to reconnect to the payment chain.
+pub fn retry_delay(attempt: u32) -> Duration {
+	MIN_RETRY_DELAY
+		.saturating_mul(2u32.saturating_pow(attempt))
+		.min(MAX_RETRY_DELAY)
+}
+
+/// Applies the payments made in the finalized blocks of the payment chain until the subscription
+/// ends.
+///
+/// The blocks are processed in order starting after the last processed one, so the blocks
+/// finalized since then are backfilled first. Without a processed block, the processing starts at
+/// the latest finalized block.
+pub async fn follow_payments<C: PaymentChain>(
+	chain: &mut C,
+	relay_chain: &RelayChain,
+	payment_info: &PaymentInfo,
+) -> Result<(), WatcherError> {
+	let finalized = chain.finalized_block().await?;
+	process_blocks_until(chain, relay_chain, payment_info, finalized).await?;
+
+	while let Some(finalized) = chain.next_finalized_block().await {
+		process_blocks_until(chain, relay_chain, payment_info, finalized?).await?;
+	}
+
+	Ok(())
+}
+
+async fn process_blocks_until<C: PaymentChain>(
+	chain: &C,
+	relay_chain: &RelayChain,
+	payment_info: &PaymentInfo,
+	finalized: BlockNumber,
+) -> Result<(), WatcherError> {
+	let first = match last_processed_block(relay_chain)? {
+		Some(block_number) => block_number.saturating_add(1),
+		None => finalized,
+	};
+
+	for block_number in first..=finalized {
+		let payments = chain.payments_at(block_number).await?;
+		// The block is processed again if a payment failed for a reason which may go away.
+		apply_block_payments(relay_chain, block_number, payments, payment_info)?;
+		save_processed_block(relay_chain, block_number)?;
+	}
+
+	Ok(())
+}
+
+/// Applies the payments made in a block of the payment chain of `relay_chain`.
+///
+/// A payment which can never be applied is skipped, while the first payment failing for another
+/// reason stops the processing and its error is returned. The payments which were already applied
+/// are recorded in the ledger, so processing the same block again is harmless.
+fn apply_block_payments(
+	relay_chain: &RelayChain,
+	block_number: BlockNumber,
+	payments: Vec<DetectedPayment>,
+	payment_info: &PaymentInfo,
+) -> Result<(), WatcherError> {
+	let mut registry_changed = false;
+	let mut result = Ok(());
+	for payment in payments {
+		let (para_relay_chain, para_id) = payment.para.clone();
+
+		// The payment configuration only applies to the parachains of its relay chain.
+		if &para_relay_chain != relay_chain {
+			log::warn!(
+				target: LOG_TARGET,
+				"{}-{} - Ignoring payment from block #{} made on the {} payment chain",
+				para_relay_chain,
+				para_id,
+				block_number,
+				relay_chain
+			);
+			continue;
+		}
+
+		// Failures are logged by `apply_payment`.
+		match apply_payment(payment, payment_info) {
+			Ok(para) => {
+				log::info!(
+					target: LOG_TARGET,
+					"{}-{} - Applied payment from block #{}, the subscription expires at {}",
+					para_relay_chain,
+					para_id,
+					block_number,
+					para.expiry_timestamp
+				);
+				registry_changed = true;
+			},
+			Err(err) if err.is_permanent() => {},
+			Err(err) => {
+				result = Err(err);
+				break;
+			},
+		}
+	}
+
+	if registry_changed && cfg!(not(debug_assertions)) {
+		crate::init_tracker();
+	}
+
+	result
+}
+
+/// Returns the last block processed by the watcher of the payment chain of `relay_chain`.
+pub fn last_processed_block(relay_chain: &RelayChain) -> Result<Option<BlockNumber>, WatcherError> {
+	Ok(watcher_state()?.get(relay_chain).copied())
+}
+
+fn save_processed_block(
+	relay_chain: &RelayChain,
+	block_number: BlockNumber,
+) -> Result<(), WatcherError> {
+	let _lock = STATE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+	let mut state = watcher_state()?;
+	state.insert(relay_chain.clone(), block_number);
+
+	let content = serde_json::to_vec(&state).map_err(|_| WatcherError::State)?;
+	write_atomically(&config().watcher_state, &content).map_err(|e| {
+		log::error!(target: LOG_TARGET, "Failed to write the watcher state: {:?}", e);
+		WatcherError::State
+	})
+}
+
+fn watcher_state() -> Result<HashMap<RelayChain, BlockNumber>, WatcherError> {
+	let content = match std::fs::read_to_string(config().watcher_state) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+		Err(e) => {
+			log::error!(target: LOG_TARGET, "Failed to read the watcher state: {:?}", e);
+			return Err(WatcherError::State);
+		},
+	};
+
+	serde_json::from_str(&content).map_err(|e| {
+		log::error!(target: LOG_TARGET, "Failed to parse the watcher state: {:?}", e);
+		WatcherError::State
+	})
+}
@@ -14,7 +14,7 @@
 // along with RegionX.  If not, see <https://www.gnu.org/licenses/>.
 
 use serde::{Deserialize, Deserializer, Serialize};
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 /// Timestamp based on the 1 Jan 1970 UNIX base, which is persistent across node restarts and OS
 /// reboots.
@@ -39,16 +39,25 @@ impl fmt::Display for RelayChain {
 	}
 }
 
-impl From<&str> for RelayChain {
-	fn from(s: &str) -> Self {
+impl FromStr for RelayChain {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
 		match s.to_lowercase().as_str() {
-			"polkadot" => RelayChain::Polkadot,
-			"kusama" => RelayChain::Kusama,
-			_ => panic!("Invalid relay chain: {}", s),
+			"polkadot" => Ok(RelayChain::Polkadot),
+			"kusama" => Ok(RelayChain::Kusama),
+			_ => Err(format!("Invalid relay chain: {}", s)),
 		}
 	}
 }
 
+impl From<&str> for RelayChain {
+	/// Panics if the relay chain is unknown, use `str::parse` for untrusted input.
+	fn from(s: &str) -> Self {
+		s.parse().unwrap_or_else(|e: String| panic!("{}", e))
+	}
+}
+
 impl<'de> Deserialize<'de> for RelayChain {
 	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
 	where
@@ -77,6 +86,9 @@ pub struct Parachain {
 
 	/// The timestamp when the subscription expires.
 	pub expiry_timestamp: Timestamp,
+	/// The subscription plan of the parachain, if any.
+	#[serde(default)]
+	pub plan: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -95,20 +107,20 @@ pub struct WeightConsumption {
 pub struct DispatchClassConsumption {
 	/// The percentage of the weight used by user submitted extrinsics compared to the
 	/// maximum potential.
-	pub normal: f32,
+	pub normal: f64,
 	/// The percentage of the weight used by user operational dispatches compared to the
 	/// maximum potential.
-	pub operational: f32,
+	pub operational: f64,
 	/// The percentage of the weight used by the mandatory tasks of a parachain compared
 	/// to the maximum potential.
-	pub mandatory: f32,
+	pub mandatory: f64,
 }
 
-/// A shorthand for converting a tuple of `f32`s into `DispatchClassConsumption`.
+/// A shorthand for converting a tuple of `f64`s into `DispatchClassConsumption`.
 ///
 /// The order in which the values need to be provided is: `normal`, `operational`, `mandatory`.
-impl From<(f32, f32, f32)> for DispatchClassConsumption {
-	fn from(value: (f32, f32, f32)) -> Self {
+impl From<(f64, f64, f64)> for DispatchClassConsumption {
+	fn from(value: (f64, f64, f64)) -> Self {
 		DispatchClassConsumption { normal: value.0, operational: value.1, mandatory: value.2 }
 	}
 }
@@ -147,3 +159,144 @@ impl WeightConsumption {
 		]
 	}
 }
+
+/// The granularity at which the consumption data gets rolled up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub enum RollupPeriod {
+	Minute,
+	Hour,
+	Day,
+}
+
+impl RollupPeriod {
+	/// All the periods for which rollups are computed.
+	pub const ALL: [RollupPeriod; 3] =
+		[RollupPeriod::Minute, RollupPeriod::Hour, RollupPeriod::Day];
+
+	/// The length of the period in milliseconds, which is the unit of the block timestamps.
+	pub fn duration(&self) -> Timestamp {
+		match self {
+			RollupPeriod::Minute => 60 * 1000,
+			RollupPeriod::Hour => 60 * 60 * 1000,
+			RollupPeriod::Day => 24 * 60 * 60 * 1000,
+		}
+	}
+
+	/// Returns the start of the period to which the given timestamp belongs.
+	pub fn period_start(&self, timestamp: Timestamp) -> Timestamp {
+		timestamp - timestamp % self.duration()
+	}
+}
+
+impl fmt::Display for RollupPeriod {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RollupPeriod::Minute => write!(f, "minute"),
+			RollupPeriod::Hour => write!(f, "hour"),
+			RollupPeriod::Day => write!(f, "day"),
+		}
+	}
+}
+
+#[derive(Default, Debug, Serialize, PartialEq, Deserialize, Clone)]
+pub struct ConsumptionStats {
+	/// The average consumption over the period.
+	pub mean: f64,
+	/// The lowest consumption of a single block within the period.
+	pub min: f64,
+	/// The highest consumption of a single block within the period.
+	pub max: f64,
+}
+
+#[derive(Default, Debug, Serialize, PartialEq, Deserialize, Clone)]
+pub struct DispatchClassStats {
+	/// Statistics of the weight used by user submitted extrinsics.
+	pub normal: ConsumptionStats,
+	/// Statistics of the weight used by operational dispatches.
+	pub operational: ConsumptionStats,
+	/// Statistics of the weight used by the mandatory tasks of a parachain.
+	pub mandatory: ConsumptionStats,
+}
+
+#[derive(Default, Debug, Serialize, PartialEq, Deserialize, Clone)]
+pub struct ConsumptionPercentiles {
+	/// The consumption not exceeded by half of the blocks within the period.
+	pub p50: f64,
+	/// The consumption not exceeded by 90% of the blocks within the period.
+	pub p90: f64,
+	/// The consumption not exceeded by 99% of the blocks within the period.
+	pub p99: f64,
+}
+
+#[derive(Default, Debug, Serialize, PartialEq, Deserialize, Clone)]
+pub struct DispatchClassPercentiles {
+	/// Percentiles of the weight used by user submitted extrinsics.
+	pub normal: ConsumptionPercentiles,
+	/// Percentiles of the weight used by operational dispatches.
+	pub operational: ConsumptionPercentiles,
+	/// Percentiles of the weight used by the mandatory tasks of a parachain.
+	pub mandatory: ConsumptionPercentiles,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConsumptionRollup {
+	/// The timestamp at which the rolled up period starts.
+	pub period_start: Timestamp,
+	/// The number of blocks that were rolled up.
+	pub count: usize,
+	/// The ref_time statistics over all the dispatch classes.
+	pub ref_time: DispatchClassStats,
+	/// The proof size statistics over all the dispatch classes.
+	pub proof_size: DispatchClassStats,
+	/// The ref_time percentiles over all the dispatch classes.
+	pub ref_time_percentiles: DispatchClassPercentiles,
+	/// The proof size percentiles over all the dispatch classes.
+	pub proof_size_percentiles: DispatchClassPercentiles,
+	/// The first block within the period.
+	pub first_block: u32,
+	/// The last block within the period.
+	pub last_block: u32,
+	/// The total ref_time consumed within the period per dispatch class.
+	pub ref_time_sum: DispatchClassConsumption,
+	/// The total proof size consumed within the period per dispatch class.
+	pub proof_size_sum: DispatchClassConsumption,
+}
+
+impl ConsumptionRollup {
+	/// Returns the rollup as a vector of strings, where each element represents a column in a CSV
+	/// format.
+	pub fn to_csv(&self) -> Vec<String> {
+		let mut record = vec![self.period_start.to_string(), self.count.to_string()];
+
+		[&self.ref_time, &self.proof_size].iter().for_each(|stats| {
+			[&stats.normal, &stats.operational, &stats.mandatory].iter().for_each(|class| {
+				record.push(class.mean.to_string());
+				record.push(class.min.to_string());
+				record.push(class.max.to_string());
+			})
+		});
+
+		[&self.ref_time_percentiles, &self.proof_size_percentiles]
+			.iter()
+			.for_each(|percentiles| {
+				[&percentiles.normal, &percentiles.operational, &percentiles.mandatory]
+					.iter()
+					.for_each(|class| {
+						record.push(class.p50.to_string());
+						record.push(class.p90.to_string());
+						record.push(class.p99.to_string());
+					})
+			});
+
+		record.push(self.first_block.to_string());
+		record.push(self.last_block.to_string());
+
+		[&self.ref_time_sum, &self.proof_size_sum].iter().for_each(|sum| {
+			record.push(sum.normal.to_string());
+			record.push(sum.operational.to_string());
+			record.push(sum.mandatory.to_string());
+		});
+
+		record
+	}
+}